@@ -1,23 +1,48 @@
-use crate::language::Language;
+use crate::{
+    language::Language,
+    state::instance::{AbsenceKind, RoundingRule},
+};
 use chrono_tz::Tz;
 use render::DocFormat;
 use std::ops::Range;
-use time_util::{TimeHintDay, TimeHintMinute, TimeHintMonth};
+use time_util::{TimeHintDay, TimeHintMinute, TimeHintMonth, TimeHintWeek, WeekStart};
 
 mod parser;
 
-pub use parser::parse;
+pub use parser::{ParseError, parse};
 
 #[derive(Debug, Clone)]
 pub enum Command {
     Help,
     Nope,
+    Status,
+    /// Spans of the current day, with the time since an open entry
+    Today,
+    /// Time zones that can be given by the name of their city
+    TimeZones,
+    Persons,
+    RemovePerson {
+        target: Target,
+    },
+    SetAdmin {
+        target: Target,
+        admin: bool,
+    },
     Clear {
         day: Range<i64>,
     },
     ClearHint {
         day: TimeHintDay,
     },
+    ConfirmClear,
+    Absence {
+        day: Range<i64>,
+        kind: AbsenceKind,
+    },
+    AbsenceHint {
+        day: TimeHintDay,
+        kind: AbsenceKind,
+    },
     Span {
         enter: i64,
         leave: i64,
@@ -32,23 +57,45 @@ pub enum Command {
         enter: i64,
     },
     EnterHint {
+        /// Today when not given
+        day: Option<TimeHintDay>,
         time_hint: TimeHintMinute,
     },
     Leave {
         leave: i64,
     },
     LeaveHint {
+        /// Today when not given
+        day: Option<TimeHintDay>,
         time_hint: TimeHintMinute,
     },
+    WeekHint {
+        time_hint: TimeHintWeek,
+        /// Preference of the person when not given
+        format: Option<DocFormat>,
+        all: bool,
+        /// Zone the document is shown in, the one of the instance when not given
+        time_zone: Option<Tz>,
+    },
+    Week {
+        week: Range<i64>,
+        format: DocFormat,
+        all: bool,
+        time_zone: Option<Tz>,
+    },
     MonthHint {
         time_hint: TimeHintMonth,
-        format: DocFormat,
+        /// Preference of the person when not given
+        format: Option<DocFormat>,
         all: bool,
+        /// Zone the document is shown in, the one of the instance when not given
+        time_zone: Option<Tz>,
     },
     Month {
         month: Range<i64>,
         format: DocFormat,
         all: bool,
+        time_zone: Option<Tz>,
     },
     SetTimeZone {
         time_zone: Tz,
@@ -56,4 +103,83 @@ pub enum Command {
     SetLanguage {
         language: Language,
     },
+    /// Contracted hours per month, zero removes the contract
+    SetHours {
+        hours: u32,
+    },
+    SetRounding {
+        rounding: RoundingRule,
+    },
+    /// Longest break, in minutes, joined into the spans around it, zero disables it
+    SetMergeGap {
+        minutes: u32,
+    },
+    SetWeekStart {
+        week_start: WeekStart,
+    },
+    /// Format of the documents of the person when not given in the command
+    SetFormat {
+        format: DocFormat,
+    },
+    /// Name shown instead of the one from the Telegram profile
+    SetName {
+        name: String,
+    },
+    /// Group the private messages of the person apply to
+    Switch {
+        group: String,
+    },
+}
+
+/// Time zones known by a city, with its english and spanish names, matched ignoring case,
+/// accents and spaces
+pub const TIME_ZONES: [(&str, &str, Tz); 22] = [
+    ("London", "Londres", Tz::Europe__London),
+    ("Lisbon", "Lisboa", Tz::Europe__Lisbon),
+    ("Madrid", "Madrid", Tz::Europe__Madrid),
+    ("Canary Islands", "Canarias", Tz::Atlantic__Canary),
+    ("Paris", "París", Tz::Europe__Paris),
+    ("Brussels", "Bruselas", Tz::Europe__Brussels),
+    ("Amsterdam", "Ámsterdam", Tz::Europe__Amsterdam),
+    ("Berlin", "Berlín", Tz::Europe__Berlin),
+    ("Rome", "Roma", Tz::Europe__Rome),
+    ("Athens", "Atenas", Tz::Europe__Athens),
+    ("Moscow", "Moscú", Tz::Europe__Moscow),
+    ("New York", "Nueva York", Tz::America__New_York),
+    ("Chicago", "Chicago", Tz::America__Chicago),
+    ("Denver", "Denver", Tz::America__Denver),
+    ("Los Angeles", "Los Ángeles", Tz::America__Los_Angeles),
+    ("Mexico City", "Ciudad de México", Tz::America__Mexico_City),
+    ("Bogota", "Bogotá", Tz::America__Bogota),
+    ("Lima", "Lima", Tz::America__Lima),
+    ("Santiago", "Santiago de Chile", Tz::America__Santiago),
+    (
+        "Buenos Aires",
+        "Buenos Aires",
+        Tz::America__Argentina__Buenos_Aires,
+    ),
+    ("Tokyo", "Tokio", Tz::Asia__Tokyo),
+    ("Sydney", "Sídney", Tz::Australia__Sydney),
+];
+
+impl Command {
+    /// Whether the command runs again when its message is edited
+    ///
+    /// An edited entry replaces the pending one, or opens a new one if the person left in
+    /// the meantime. An edited span is recorded as a new one, the original is only replaced
+    /// where they overlap.
+    pub fn runs_when_edited(&self) -> bool {
+        matches!(
+            self,
+            Self::Enter { .. } | Self::EnterHint { .. } | Self::Span { .. } | Self::SpanHint { .. }
+        )
+    }
+}
+
+/// Person designated in a command, an index refers to the `persons` listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Me,
+    All,
+    Index(usize),
 }
@@ -3,15 +3,35 @@ use chrono_tz::Tz;
 use pest::Parser;
 use pest::RuleType;
 use pest::iterators::Pair;
+use std::borrow::Cow;
 use time_util::TimeHintDay;
 use time_util::TimeHintMinute;
 use time_util::TimeHintMonth;
+use time_util::TimeHintWeek;
+use time_util::WeekStart;
 use tracing::error;
 use tracing::warn;
 use unicode_normalization::UnicodeNormalization;
 
 use crate::command::DocFormat;
-use crate::{command::Command, language::Language};
+use crate::{
+    command::{Command, TIME_ZONES, Target},
+    language::Language,
+    state::instance::{AbsenceKind, RoundingRule},
+};
+
+/// Reason a text is not a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Unrecognized,
+    /// The command is well formed but names a time zone that does not exist
+    UnknownTimeZone(String),
+}
+impl From<()> for ParseError {
+    fn from(_: ()) -> Self {
+        Self::Unrecognized
+    }
+}
 
 pub mod en {
     use pest_derive::Parser;
@@ -72,13 +92,31 @@ common_node!(
         EOI,
         WHITESPACE,
         TIME_ZONE,
+        IN,
         CLEAR,
+        HOLIDAY,
+        SICK,
+        VACATION,
+        CONFIRM,
         NEW,
+        REMOVE,
         ADMIN,
         SET,
         HELP,
+        STATUS,
+        TODAY,
+        TIME_ZONES,
         PERSON,
         LANGUAGE,
+        HOURS,
+        ROUNDING,
+        FORMAT,
+        MERGE_GAP,
+        START,
+        UP,
+        DOWN,
+        NAME,
+        SWITCH,
         PERSONS,
         TARGET_ALL,
         TARGET_ME,
@@ -86,6 +124,35 @@ common_node!(
         FALSE,
         ENTER,
         LEAVE,
+        WEEK,
+        LAST,
+        YESTERDAY,
+        AT,
+        HALF,
+        HOUR_00,
+        HOUR_01,
+        HOUR_02,
+        HOUR_03,
+        HOUR_04,
+        HOUR_05,
+        HOUR_06,
+        HOUR_07,
+        HOUR_08,
+        HOUR_09,
+        HOUR_10,
+        HOUR_11,
+        HOUR_12,
+        HOUR_13,
+        HOUR_14,
+        HOUR_15,
+        HOUR_16,
+        HOUR_17,
+        HOUR_18,
+        HOUR_19,
+        HOUR_20,
+        HOUR_21,
+        HOUR_22,
+        HOUR_23,
         MONTH,
         MONTH_01,
         MONTH_02,
@@ -107,14 +174,23 @@ common_node!(
         WEEKDAY_5,
         WEEKDAY_6,
         PDF,
+        PNG,
         month_options,
+        display_zone,
+        doc_format,
+        absence_kind,
+        rounding_kind,
         word,
         hour_minute,
         number,
         year,
         year_month,
         month_year,
+        year_week,
+        week_number,
         time_zone,
+        zone_word,
+        group_name,
         name,
         bool,
         targets,
@@ -123,24 +199,48 @@ common_node!(
         month,
         command,
         command_help,
+        command_status,
+        command_today,
+        command_time_zones,
         command_persons,
         command_person_admin,
+        command_person_remove,
         command_new_person,
         command_set_time_zone,
         command_set_language,
+        command_set_hours,
+        command_set_rounding,
+        command_set_week_start,
+        command_set_format,
+        command_set_merge_gap,
+        command_set_name,
+        command_switch,
         command_clear,
+        command_confirm_clear,
+        command_absence,
         command_clear_date,
         command_span,
         command_span_date,
         command_span_date_date,
         command_enter,
         command_enter_hour_minute,
+        command_enter_date,
+        command_enter_spoken,
         command_leave,
         command_leave_hour_minute,
+        command_leave_date,
+        command_leave_spoken,
+        command_week,
+        command_week_last,
+        command_week_year_week,
         command_month,
+        command_month_last,
+        command_month_offset,
         command_month_month,
         command_month_year_month,
         weekday,
+        hour_word,
+        spoken_time,
         day,
         date_sep,
         year_month_day,
@@ -149,14 +249,27 @@ common_node!(
     ]
 );
 
-pub fn parse(language: Language, s: &str) -> Result<Command, ()> {
+pub fn parse(language: Language, s: &str) -> Result<Command, ParseError> {
+    let s = strip_bot_username(s);
     match language {
-        Language::En => parse_typed::<en::CommandParser, en::Rule>(s),
-        Language::Es => parse_typed::<es::CommandParser, es::Rule>(s),
+        Language::En => parse_typed::<en::CommandParser, en::Rule>(&s),
+        Language::Es => parse_typed::<es::CommandParser, es::Rule>(&s),
+    }
+}
+
+/// Removes the username Telegram appends to a slash command in groups, as in `/enter@bot`
+fn strip_bot_username(s: &str) -> Cow<'_, str> {
+    let Some(command) = s.trim_start().strip_prefix('/') else {
+        return Cow::Borrowed(s);
+    };
+    let end = command.find(char::is_whitespace).unwrap_or(command.len());
+    match command[..end].split_once('@') {
+        Some((name, _)) => Cow::Owned(format!("/{name}{}", &command[end..])),
+        None => Cow::Borrowed(s),
     }
 }
 
-fn parse_typed<P, R>(s: &str) -> Result<Command, ()>
+fn parse_typed<P, R>(s: &str) -> Result<Command, ParseError>
 where
     P: Parser<R>,
     R: RuleType + From<Node> + Into<Node>,
@@ -167,6 +280,20 @@ where
 
             Ok(match command.as_rule().into() {
                 Node::command_help => Command::Help,
+                Node::command_status => Command::Status,
+                Node::command_today => Command::Today,
+                Node::command_time_zones => Command::TimeZones,
+                Node::command_persons => Command::Persons,
+                Node::command_person_remove => Command::RemovePerson {
+                    target: parse_target(command.child())?,
+                },
+                Node::command_person_admin => {
+                    let [target, admin] = command.children();
+                    Command::SetAdmin {
+                        target: parse_target(target)?,
+                        admin: parse_bool(admin),
+                    }
+                }
                 Node::command_span => {
                     let [enter, leave] = command.children();
                     let [hour, minute] = enter.children();
@@ -185,11 +312,27 @@ where
                 Node::command_clear => Command::ClearHint {
                     day: TimeHintDay::None,
                 },
+                Node::command_confirm_clear => Command::ConfirmClear,
                 Node::command_clear_date => {
                     let date = command.child();
                     let day = parse_date_hint(date);
                     Command::ClearHint { day }
                 }
+                Node::command_absence => {
+                    let [kind, date] = command.children();
+                    Command::AbsenceHint {
+                        day: parse_date_hint(date),
+                        kind: match kind.child().as_rule().into() {
+                            Node::HOLIDAY => AbsenceKind::Holiday,
+                            Node::SICK => AbsenceKind::Sick,
+                            Node::VACATION => AbsenceKind::Vacation,
+                            node => {
+                                error!("unexpected node during parsing: {node:?}");
+                                return Err(ParseError::Unrecognized);
+                            }
+                        },
+                    }
+                }
                 Node::command_span_date => {
                     let [date, enter, leave] = command.children();
                     let [hour, minute] = enter.children().map(parse_u32);
@@ -219,44 +362,123 @@ where
                     }
                 }
                 Node::command_enter => Command::EnterHint {
+                    day: None,
                     time_hint: TimeHintMinute::None,
                 },
                 Node::command_leave => Command::LeaveHint {
+                    day: None,
                     time_hint: TimeHintMinute::None,
                 },
                 Node::command_enter_hour_minute => {
                     let [hour, minute] = command.child().children();
                     Command::EnterHint {
+                        day: None,
                         time_hint: TimeHintMinute::HourMinute(parse_u32(hour), parse_u32(minute)),
                     }
                 }
                 Node::command_leave_hour_minute => {
                     let [hour, minute] = command.child().children();
                     Command::LeaveHint {
+                        day: None,
                         time_hint: TimeHintMinute::HourMinute(parse_u32(hour), parse_u32(minute)),
                     }
                 }
+                Node::command_enter_date => {
+                    let [date, time] = command.children();
+                    let [hour, minute] = time.children().map(parse_u32);
+                    Command::EnterHint {
+                        day: Some(parse_date_hint(date)),
+                        time_hint: TimeHintMinute::HourMinute(hour, minute),
+                    }
+                }
+                Node::command_leave_date => {
+                    let [date, time] = command.children();
+                    let [hour, minute] = time.children().map(parse_u32);
+                    Command::LeaveHint {
+                        day: Some(parse_date_hint(date)),
+                        time_hint: TimeHintMinute::HourMinute(hour, minute),
+                    }
+                }
+                Node::command_enter_spoken => Command::EnterHint {
+                    day: None,
+                    time_hint: parse_spoken_time(command.child()),
+                },
+                Node::command_leave_spoken => Command::LeaveHint {
+                    day: None,
+                    time_hint: parse_spoken_time(command.child()),
+                },
+                Node::command_week => {
+                    let (format, all, time_zone) = parse_month_options(command.child())?;
+                    Command::WeekHint {
+                        time_hint: TimeHintWeek::None,
+                        format,
+                        all,
+                        time_zone,
+                    }
+                }
+                Node::command_week_last => {
+                    let (format, all, time_zone) = parse_month_options(command.child())?;
+                    Command::WeekHint {
+                        time_hint: TimeHintWeek::Last,
+                        format,
+                        all,
+                        time_zone,
+                    }
+                }
+                Node::command_week_year_week => {
+                    let [year_week, options] = command.children();
+                    let (format, all, time_zone) = parse_month_options(options)?;
+                    let [year, week] = year_week.children();
+                    Command::WeekHint {
+                        time_hint: TimeHintWeek::YearWeek(parse_year(year), parse_week(week)),
+                        format,
+                        all,
+                        time_zone,
+                    }
+                }
                 Node::command_month => {
                     let options = command.child();
-                    let (format, all) = parse_month_options(options);
+                    let (format, all, time_zone) = parse_month_options(options)?;
                     Command::MonthHint {
                         time_hint: TimeHintMonth::None,
                         format,
                         all,
+                        time_zone,
+                    }
+                }
+                Node::command_month_last => {
+                    let (format, all, time_zone) = parse_month_options(command.child())?;
+                    Command::MonthHint {
+                        time_hint: TimeHintMonth::MonthOffset(-1),
+                        format,
+                        all,
+                        time_zone,
+                    }
+                }
+                Node::command_month_offset => {
+                    let [offset, options] = command.children();
+                    let (format, all, time_zone) = parse_month_options(options)?;
+                    let offset: i32 = offset.as_str().parse().map_err(|_| ())?;
+                    Command::MonthHint {
+                        time_hint: TimeHintMonth::MonthOffset(-offset),
+                        format,
+                        all,
+                        time_zone,
                     }
                 }
                 Node::command_month_month => {
                     let [month, options] = command.children();
-                    let (format, all) = parse_month_options(options);
+                    let (format, all, time_zone) = parse_month_options(options)?;
                     Command::MonthHint {
                         time_hint: TimeHintMonth::Month(parse_month(month)),
                         format,
                         all,
+                        time_zone,
                     }
                 }
                 Node::command_month_year_month => {
                     let [month, options] = command.children();
-                    let (format, all) = parse_month_options(options);
+                    let (format, all, time_zone) = parse_month_options(options)?;
                     let order = month.as_rule().into();
                     let [lhs, rhs] = month.children();
                     let (year, month) = match order {
@@ -268,6 +490,7 @@ where
                         time_hint: TimeHintMonth::YearMonth(parse_year(year), parse_month(month)),
                         format,
                         all,
+                        time_zone,
                     }
                 }
                 Node::command_set_time_zone => {
@@ -282,27 +505,82 @@ where
                         language: parse_language(language)?,
                     }
                 }
+                Node::command_set_hours => Command::SetHours {
+                    hours: command.child().as_str().parse().map_err(|_| ())?,
+                },
+                Node::command_set_rounding => {
+                    let mut children = command.into_inner();
+                    let mut next = children.next().unwrap();
+                    let kind = match next.as_rule().into() {
+                        Node::rounding_kind => {
+                            let kind = next.child().as_rule().into();
+                            next = children.next().unwrap();
+                            Some(kind)
+                        }
+                        _ => None,
+                    };
+                    let minutes: u32 = next.as_str().parse().map_err(|_| ())?;
+                    Command::SetRounding {
+                        rounding: match kind {
+                            _ if minutes == 0 => RoundingRule::None,
+                            Some(Node::UP) => RoundingRule::Up(minutes),
+                            Some(Node::DOWN) => RoundingRule::Down(minutes),
+                            _ => RoundingRule::Nearest(minutes),
+                        },
+                    }
+                }
+                Node::command_set_week_start => Command::SetWeekStart {
+                    week_start: match command.child().child().as_rule().into() {
+                        Node::WEEKDAY_0 => WeekStart::Monday,
+                        Node::WEEKDAY_6 => WeekStart::Sunday,
+                        _ => return Err(ParseError::Unrecognized),
+                    },
+                },
+                Node::command_set_merge_gap => Command::SetMergeGap {
+                    minutes: command.child().as_str().parse().map_err(|_| ())?,
+                },
+                Node::command_set_format => Command::SetFormat {
+                    format: parse_doc_format(command.child()),
+                },
+                Node::command_set_name => Command::SetName {
+                    name: command
+                        .into_inner()
+                        .map(|name| name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                },
+                Node::command_switch => Command::Switch {
+                    group: command.child().as_str().trim().to_string(),
+                },
                 node => {
                     error!("unexpected node during parsing: {node:?}");
-                    return Err(());
+                    return Err(ParseError::Unrecognized);
                 }
             })
         }
-        Err(_) => Err(()),
+        Err(_) => Err(ParseError::Unrecognized),
     }
 }
 
-fn parse_month_options<R>(node: Pair<R>) -> (DocFormat, bool)
+/// The format is left to the preference of the person, and the time zone to the one of
+/// the instance, when not given
+fn parse_month_options<R>(
+    node: Pair<R>,
+) -> Result<(Option<DocFormat>, bool, Option<Tz>), ParseError>
 where
     R: RuleType + Into<Node>,
 {
     debug_assert_eq!(node.as_rule().into(), Node::month_options);
     let mut all = false;
-    let mut doc = DocFormat::Png;
+    let mut doc = None;
+    let mut time_zone = None;
     for node in node.into_inner() {
         match node.as_rule().into() {
-            Node::PDF => {
-                doc = DocFormat::Pdf;
+            Node::doc_format => {
+                doc = Some(parse_doc_format(node));
+            }
+            Node::display_zone => {
+                time_zone = Some(parse_time_zone(node.child())?);
             }
             Node::TARGET_ALL => {
                 all = true;
@@ -312,7 +590,18 @@ where
             }
         }
     }
-    (doc, all)
+    Ok((doc, all, time_zone))
+}
+
+fn parse_doc_format<R>(node: Pair<R>) -> DocFormat
+where
+    R: RuleType + Into<Node>,
+{
+    debug_assert_eq!(node.as_rule().into(), Node::doc_format);
+    match node.child().as_rule().into() {
+        Node::PDF => DocFormat::Pdf,
+        _ => DocFormat::Png,
+    }
 }
 
 fn parse_month<R>(node: Pair<R>) -> u32
@@ -335,6 +624,60 @@ where
         _ => unreachable!(),
     }
 }
+fn parse_spoken_time<R>(node: Pair<R>) -> TimeHintMinute
+where
+    R: RuleType + Into<Node>,
+{
+    debug_assert_eq!(node.as_rule().into(), Node::spoken_time);
+    let mut hour = 0;
+    let mut half = false;
+    for child in node.into_inner() {
+        match child.as_rule().into() {
+            Node::HALF => half = true,
+            Node::hour_word => hour = parse_hour_word(child.child()),
+            _ => unreachable!(),
+        }
+    }
+    if half {
+        TimeHintMinute::HourMinute(hour, 30)
+    } else {
+        TimeHintMinute::Hour(hour)
+    }
+}
+
+fn parse_hour_word<R>(node: Pair<R>) -> u32
+where
+    R: RuleType + Into<Node>,
+{
+    match node.as_rule().into() {
+        Node::HOUR_00 => 0,
+        Node::HOUR_01 => 1,
+        Node::HOUR_02 => 2,
+        Node::HOUR_03 => 3,
+        Node::HOUR_04 => 4,
+        Node::HOUR_05 => 5,
+        Node::HOUR_06 => 6,
+        Node::HOUR_07 => 7,
+        Node::HOUR_08 => 8,
+        Node::HOUR_09 => 9,
+        Node::HOUR_10 => 10,
+        Node::HOUR_11 => 11,
+        Node::HOUR_12 => 12,
+        Node::HOUR_13 => 13,
+        Node::HOUR_14 => 14,
+        Node::HOUR_15 => 15,
+        Node::HOUR_16 => 16,
+        Node::HOUR_17 => 17,
+        Node::HOUR_18 => 18,
+        Node::HOUR_19 => 19,
+        Node::HOUR_20 => 20,
+        Node::HOUR_21 => 21,
+        Node::HOUR_22 => 22,
+        Node::HOUR_23 => 23,
+        _ => unreachable!(),
+    }
+}
+
 fn parse_date_hint<R>(node: Pair<R>) -> TimeHintDay
 where
     R: RuleType + Into<Node>,
@@ -342,6 +685,7 @@ where
     debug_assert_eq!(node.as_rule().into(), Node::date_hint);
     let hint = node.child();
     match hint.as_rule().into() {
+        Node::YESTERDAY => TimeHintDay::Yesterday,
         Node::weekday => {
             let weekday = hint.child();
             match weekday.as_rule().into() {
@@ -372,18 +716,30 @@ where
         _ => unreachable!(),
     }
 }
-// fn parse_bool<R>(node: Pair<R>) -> bool
-// where
-//     R: RuleType + Into<Node>,
-// {
-//     assert_eq!(node.as_rule().into(), Node::bool);
-//     let node = node.into_inner().next().unwrap();
-//     match node.as_rule().into() {
-//         Node::bool_true => true,
-//         Node::bool_false => false,
-//         _ => panic!(),
-//     }
-// }
+fn parse_bool<R>(node: Pair<R>) -> bool
+where
+    R: RuleType + Into<Node>,
+{
+    debug_assert_eq!(node.as_rule().into(), Node::bool);
+    match node.child().as_rule().into() {
+        Node::TRUE => true,
+        Node::FALSE => false,
+        _ => unreachable!(),
+    }
+}
+fn parse_target<R>(node: Pair<R>) -> Result<Target, ()>
+where
+    R: RuleType + Into<Node>,
+{
+    debug_assert_eq!(node.as_rule().into(), Node::target);
+    let target = node.child();
+    Ok(match target.as_rule().into() {
+        Node::TARGET_ME => Target::Me,
+        Node::TARGET_ALL => Target::All,
+        Node::target_index => Target::Index(target.as_str().parse().map_err(|_| ())?),
+        _ => unreachable!(),
+    })
+}
 fn parse_u32<R>(node: Pair<R>) -> u32
 where
     R: RuleType + Into<Node>,
@@ -405,15 +761,28 @@ where
     debug_assert_eq!(node.as_rule().into(), Node::year);
     node.as_str().parse().unwrap()
 }
-fn parse_time_zone<R>(node: Pair<R>) -> Result<Tz, ()>
+fn parse_week<R>(node: Pair<R>) -> u32
+where
+    R: RuleType + Into<Node>,
+{
+    debug_assert_eq!(node.as_rule().into(), Node::week_number);
+    node.as_str().parse().unwrap()
+}
+fn parse_time_zone<R>(node: Pair<R>) -> Result<Tz, ParseError>
 where
     R: RuleType + Into<Node>,
 {
     debug_assert_eq!(node.as_rule().into(), Node::time_zone);
-    match node.as_str() {
-        "paris" | "Paris" => Ok(Tz::Europe__Paris),
-        "madrid" | "Madrid" => Ok(Tz::Europe__Madrid),
-        time_zone => time_zone.parse().map_err(|_| ()),
+    let time_zone = node.as_str();
+    let name = time_zone.normalize();
+    let alias = TIME_ZONES
+        .iter()
+        .find(|(en, es, _)| en.normalize() == name || es.normalize() == name);
+    match alias {
+        Some(&(_, _, time_zone)) => Ok(time_zone),
+        None => time_zone
+            .parse()
+            .map_err(|_| ParseError::UnknownTimeZone(time_zone.to_string())),
     }
 }
 fn parse_language<R>(node: Pair<R>) -> Result<Language, ()>
@@ -473,3 +842,443 @@ fn test_string_normalization() {
     assert_eq!("marché".normalize(), "marche");
     assert_eq!("ESPAÑOL".normalize(), "espanol");
 }
+
+#[test]
+fn test_parse_persons() {
+    assert!(matches!(
+        parse(Language::En, "persons"),
+        Ok(Command::Persons)
+    ));
+    assert!(matches!(
+        parse(Language::En, "person @2 remove"),
+        Ok(Command::RemovePerson {
+            target: Target::Index(2)
+        })
+    ));
+    assert!(matches!(
+        parse(Language::En, "person @me admin true"),
+        Ok(Command::SetAdmin {
+            target: Target::Me,
+            admin: true
+        })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "persona @3 jefe no"),
+        Ok(Command::SetAdmin {
+            target: Target::Index(3),
+            admin: false
+        })
+    ));
+}
+
+#[test]
+fn test_parse_set_hours() {
+    assert!(matches!(
+        parse(Language::En, "set hours 160"),
+        Ok(Command::SetHours { hours: 160 })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "pon horas 120"),
+        Ok(Command::SetHours { hours: 120 })
+    ));
+}
+
+#[test]
+fn test_parse_set_name() {
+    assert!(matches!(
+        parse(Language::En, "name Alice"),
+        Ok(Command::SetName { name }) if name == "Alice"
+    ));
+    assert!(matches!(
+        parse(Language::Es, "nombre María José"),
+        Ok(Command::SetName { name }) if name == "María José"
+    ));
+}
+
+#[test]
+fn test_parse_switch() {
+    assert!(matches!(
+        parse(Language::En, "switch Night shift"),
+        Ok(Command::Switch { group }) if group == "Night shift"
+    ));
+    assert!(matches!(
+        parse(Language::Es, "cambia Turno de noche"),
+        Ok(Command::Switch { group }) if group == "Turno de noche"
+    ));
+}
+
+#[test]
+fn test_parse_absence() {
+    assert!(matches!(
+        parse(Language::En, "holiday 2025-08-15"),
+        Ok(Command::AbsenceHint {
+            day: TimeHintDay::YearMonthDay(2025, 8, 15),
+            kind: AbsenceKind::Holiday,
+        })
+    ));
+    assert!(matches!(
+        parse(Language::En, "sick monday"),
+        Ok(Command::AbsenceHint {
+            day: TimeHintDay::Weekday(Weekday::Mon),
+            kind: AbsenceKind::Sick,
+        })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "vacaciones 3"),
+        Ok(Command::AbsenceHint {
+            day: TimeHintDay::Day(3),
+            kind: AbsenceKind::Vacation,
+        })
+    ));
+}
+
+#[test]
+fn test_parse_set_rounding() {
+    for (language, text, expect) in [
+        (Language::En, "set rounding 15", RoundingRule::Nearest(15)),
+        (Language::En, "set rounding up 5", RoundingRule::Up(5)),
+        (Language::En, "set rounding 0", RoundingRule::None),
+        (
+            Language::Es,
+            "pon redondeo abajo 10",
+            RoundingRule::Down(10),
+        ),
+    ] {
+        assert!(
+            matches!(parse(language, text), Ok(Command::SetRounding { rounding }) if rounding == expect),
+            "{text}"
+        );
+    }
+}
+
+#[test]
+fn test_parse_enter_date() {
+    assert!(matches!(
+        parse(Language::En, "enter yesterday 18h30"),
+        Ok(Command::EnterHint {
+            day: Some(TimeHintDay::Yesterday),
+            time_hint: TimeHintMinute::HourMinute(18, 30),
+        })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "sale ayer 20:00"),
+        Ok(Command::LeaveHint {
+            day: Some(TimeHintDay::Yesterday),
+            time_hint: TimeHintMinute::HourMinute(20, 0),
+        })
+    ));
+    assert!(matches!(
+        parse(Language::En, "enter friday 9h00"),
+        Ok(Command::EnterHint {
+            day: Some(TimeHintDay::Weekday(Weekday::Fri)),
+            time_hint: TimeHintMinute::HourMinute(9, 0),
+        })
+    ));
+    assert!(matches!(
+        parse(Language::En, "enter 9h00"),
+        Ok(Command::EnterHint {
+            day: None,
+            time_hint: TimeHintMinute::HourMinute(9, 0),
+        })
+    ));
+    // a span on a day still takes precedence
+    assert!(matches!(
+        parse(Language::En, "yesterday 9h00 17h00"),
+        Ok(Command::SpanHint {
+            enter_day: Some(TimeHintDay::Yesterday),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parse_spoken_time() {
+    for (language, text, expect) in [
+        (Language::En, "enter at six", (6, None)),
+        (Language::En, "enter at sixteen", (16, None)),
+        (Language::En, "leave at twenty-one o'clock", (21, None)),
+        (Language::En, "enter at half past six", (6, Some(30))),
+        (Language::En, "leave noon", (12, None)),
+        (Language::Es, "entra a las seis", (6, None)),
+        (Language::Es, "sale a la una", (1, None)),
+        (Language::Es, "sale a las seis y media", (6, Some(30))),
+        (Language::Es, "entro a las dieciséis", (16, None)),
+    ] {
+        let time_hint = match parse(language, text) {
+            Ok(Command::EnterHint { time_hint, .. } | Command::LeaveHint { time_hint, .. }) => {
+                time_hint
+            }
+            other => panic!("{text}: {other:?}"),
+        };
+        match (time_hint, expect) {
+            (TimeHintMinute::Hour(hour), (expected, None)) => assert_eq!(hour, expected, "{text}"),
+            (TimeHintMinute::HourMinute(hour, minute), (expected, Some(expected_minute))) => {
+                assert_eq!((hour, minute), (expected, expected_minute), "{text}")
+            }
+            (time_hint, _) => panic!("{text}: {time_hint:?}"),
+        }
+    }
+    assert!(parse(Language::En, "enter at sixty").is_err());
+}
+
+#[test]
+fn test_parse_set_week_start() {
+    assert!(matches!(
+        parse(Language::En, "set week start sunday"),
+        Ok(Command::SetWeekStart {
+            week_start: WeekStart::Sunday
+        })
+    ));
+    assert!(matches!(
+        parse(Language::En, "set week starts monday"),
+        Ok(Command::SetWeekStart {
+            week_start: WeekStart::Monday
+        })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "pon semana empieza lunes"),
+        Ok(Command::SetWeekStart {
+            week_start: WeekStart::Monday
+        })
+    ));
+    assert!(parse(Language::En, "set week start friday").is_err());
+}
+
+#[test]
+fn test_parse_month_offset() {
+    for (language, text, expect) in [
+        (Language::En, "month last", -1),
+        (Language::En, "month last pdf", -1),
+        (Language::En, "month -2", -2),
+        (Language::Es, "mes pasado", -1),
+        (Language::Es, "mes -13 todos", -13),
+    ] {
+        assert!(
+            matches!(
+                parse(language, text),
+                Ok(Command::MonthHint {
+                    time_hint: TimeHintMonth::MonthOffset(offset),
+                    ..
+                }) if offset == expect
+            ),
+            "{text:?}"
+        );
+    }
+}
+
+#[test]
+fn test_parse_week() {
+    for (language, text) in [
+        (Language::En, "week"),
+        (Language::En, "week last pdf"),
+        (Language::En, "week 2025-W03 all"),
+        (Language::En, "week 2025w3"),
+        (Language::Es, "semana"),
+        (Language::Es, "semana pasada"),
+        (Language::Es, "semana 2025-W03 todos"),
+    ] {
+        assert!(
+            matches!(parse(language, text), Ok(Command::WeekHint { .. })),
+            "{text:?}"
+        );
+    }
+    assert!(matches!(
+        parse(Language::En, "week 2025-W03 pdf"),
+        Ok(Command::WeekHint {
+            time_hint: TimeHintWeek::YearWeek(2025, 3),
+            format: Some(DocFormat::Pdf),
+            all: false,
+            time_zone: None,
+        })
+    ));
+}
+
+#[test]
+fn test_parse_format() {
+    for (language, text, expect) in [
+        (Language::En, "month", None),
+        (Language::En, "month pdf", Some(DocFormat::Pdf)),
+        (Language::En, "month 2025/08 png", Some(DocFormat::Png)),
+        (Language::Es, "mes pdf", Some(DocFormat::Pdf)),
+        (Language::Es, "mes pasado png todos", Some(DocFormat::Png)),
+    ] {
+        assert!(
+            matches!(parse(language, text), Ok(Command::MonthHint { format, .. }) if format == expect),
+            "{text:?}"
+        );
+    }
+    assert!(matches!(
+        parse(Language::En, "set format pdf"),
+        Ok(Command::SetFormat {
+            format: DocFormat::Pdf
+        })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "pon formato png"),
+        Ok(Command::SetFormat {
+            format: DocFormat::Png
+        })
+    ));
+    assert!(parse(Language::En, "set format gif").is_err());
+}
+
+#[test]
+fn test_parse_today() {
+    assert!(matches!(parse(Language::En, "today"), Ok(Command::Today)));
+    assert!(matches!(parse(Language::Es, "hoy"), Ok(Command::Today)));
+}
+
+#[test]
+fn test_parse_set_merge_gap() {
+    assert!(matches!(
+        parse(Language::En, "set merge gap 5"),
+        Ok(Command::SetMergeGap { minutes: 5 })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "pon pausa máxima 10"),
+        Ok(Command::SetMergeGap { minutes: 10 })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "pon pausa 0"),
+        Ok(Command::SetMergeGap { minutes: 0 })
+    ));
+}
+
+#[test]
+fn test_parse_display_zone() {
+    for (language, text, expect) in [
+        (Language::En, "month", None),
+        (Language::En, "month in UTC", Some(Tz::UTC)),
+        (
+            Language::En,
+            "month last in America/New_York pdf",
+            Some(Tz::America__New_York),
+        ),
+        (Language::Es, "mes zona Madrid", Some(Tz::Europe__Madrid)),
+        (Language::Es, "mes 2025/08 zona UTC todos", Some(Tz::UTC)),
+    ] {
+        assert!(
+            matches!(parse(language, text), Ok(Command::MonthHint { time_zone, .. }) if time_zone == expect),
+            "{text:?}"
+        );
+    }
+    assert!(matches!(
+        parse(Language::En, "week in UTC"),
+        Ok(Command::WeekHint {
+            time_zone: Some(Tz::UTC),
+            ..
+        })
+    ));
+    assert_eq!(
+        parse(Language::En, "month in Nowhere/Land").unwrap_err(),
+        ParseError::UnknownTimeZone("Nowhere/Land".into())
+    );
+}
+
+#[test]
+fn test_parse_slash_command() {
+    for text in ["enter", "/enter", "/enter@mybot", " /enter@my_bot "] {
+        assert!(
+            matches!(parse(Language::En, text), Ok(Command::EnterHint { .. })),
+            "{text:?}"
+        );
+    }
+    assert!(matches!(
+        parse(Language::En, "/month@mybot pdf"),
+        Ok(Command::MonthHint {
+            format: Some(DocFormat::Pdf),
+            ..
+        })
+    ));
+    assert!(matches!(
+        parse(Language::Es, "/entra@mybot 18h30"),
+        Ok(Command::EnterHint { .. })
+    ));
+    // a target is not a username
+    assert!(matches!(
+        parse(Language::En, "/person@mybot @1 remove"),
+        Ok(Command::RemovePerson { .. })
+    ));
+    assert_eq!(strip_bot_username("/person @1 remove"), "/person @1 remove");
+}
+
+#[test]
+fn test_parse_unknown_time_zone() {
+    assert!(matches!(
+        parse(Language::En, "set time zone America/New_York"),
+        Ok(Command::SetTimeZone {
+            time_zone: Tz::America__New_York
+        })
+    ));
+    assert_eq!(
+        parse(Language::En, "set time zone Madird").unwrap_err(),
+        ParseError::UnknownTimeZone("Madird".into())
+    );
+    assert_eq!(
+        parse(Language::En, "set time zone").unwrap_err(),
+        ParseError::Unrecognized
+    );
+}
+
+#[test]
+fn test_parse_time_zone_alias() {
+    for (language, text, expected) in [
+        (Language::En, "set time zone london", Tz::Europe__London),
+        (
+            Language::En,
+            "set time zone New York",
+            Tz::America__New_York,
+        ),
+        (
+            Language::En,
+            "set time zone new_york",
+            Tz::America__New_York,
+        ),
+        (
+            Language::Es,
+            "configura zona horaria Nueva York",
+            Tz::America__New_York,
+        ),
+        (
+            Language::Es,
+            "configura zona horaria berlín",
+            Tz::Europe__Berlin,
+        ),
+        (
+            Language::Es,
+            "configura zona horaria Ciudad de México",
+            Tz::America__Mexico_City,
+        ),
+    ] {
+        assert!(
+            matches!(
+                parse(language, text),
+                Ok(Command::SetTimeZone { time_zone }) if time_zone == expected
+            ),
+            "{text:?}"
+        );
+    }
+    assert!(matches!(
+        parse(Language::En, "month in new york pdf"),
+        Ok(Command::MonthHint {
+            format: Some(DocFormat::Pdf),
+            time_zone: Some(Tz::America__New_York),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parse_time_zones() {
+    for (language, text) in [
+        (Language::En, "timezones"),
+        (Language::En, "time zones"),
+        (Language::En, "/timezones"),
+        (Language::Es, "zonas horarias"),
+    ] {
+        assert!(
+            matches!(parse(language, text), Ok(Command::TimeZones)),
+            "{text:?}"
+        );
+    }
+}
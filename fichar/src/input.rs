@@ -1,17 +1,26 @@
-use telegram::{ChatMember, ChatType, Update};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use telegram::{ChatMember, ChatType, Message, Update};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Input {
     Text {
         user: (Option<String>, Option<String>),
         chat: i64,
+        thread: Option<i32>,
         group: bool,
+        /// Title of the chat, groups have one
+        title: Option<String>,
         person: i64,
+        language_code: Option<String>,
         date: i64,
         text: String,
+        /// Correction of a message already processed, only some commands run again
+        edited: bool,
     },
     NewGroup {
         chat: i64,
+        person: i64,
         name: String,
     },
     LeftChat {
@@ -20,42 +29,192 @@ pub enum Input {
     },
     NowAdmin {
         chat: i64,
+        person: i64,
     },
 }
 
+impl Input {
+    /// Person at the origin of the input
+    pub fn person(&self) -> i64 {
+        match *self {
+            Self::Text { person, .. }
+            | Self::NewGroup { person, .. }
+            | Self::LeftChat { person, .. }
+            | Self::NowAdmin { person, .. } => person,
+        }
+    }
+}
+
+impl Input {
+    fn from_message(message: Message, text: String, edited: bool) -> Self {
+        Self::Text {
+            user: (message.from.first_name, message.from.last_name),
+            chat: message.chat.id,
+            thread: message
+                .message_thread_id
+                .filter(|_| message.is_topic_message),
+            group: matches!(message.chat.kind, ChatType::Group | ChatType::SuperGroup),
+            title: message.chat.title,
+            person: message.from.id,
+            language_code: message.from.language_code,
+            date: message.date,
+            text,
+            edited,
+        }
+    }
+}
+
+/// Update that does not map to an input, with what it was for logging
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported {
+    pub kind: &'static str,
+}
+
 impl TryFrom<Update> for Input {
-    type Error = ();
+    type Error = Unsupported;
 
     fn try_from(update: Update) -> Result<Self, Self::Error> {
         if let Some(message) = update.message {
-            if let Some(text) = message.text {
+            if let Some(text) = message.text.clone() {
                 println!("{:?}", message.entities);
-                Ok(Self::Text {
-                    user: (message.from.first_name, message.from.last_name),
-                    chat: message.chat.id,
-                    group: message.chat.kind == ChatType::Group,
-                    person: message.from.id,
-                    date: message.date,
-                    text,
-                })
+                Ok(Self::from_message(message, text, false))
             } else if message.group_chat_created {
                 Ok(Self::NewGroup {
                     chat: message.chat.id,
+                    person: message.from.id,
                     name: message.chat.title.unwrap(),
                 })
             } else {
-                Err(())
+                Err(Unsupported {
+                    kind: "message without text",
+                })
             }
         } else if let Some(chat_member) = update.my_chat_member {
             if let ChatMember::Administrator { .. } = chat_member.new_chat_member {
                 Ok(Self::NowAdmin {
                     chat: chat_member.chat.id,
+                    person: chat_member.from.id,
                 })
             } else {
-                Err(())
+                Err(Unsupported {
+                    kind: "chat member update",
+                })
+            }
+        } else if let Some(callback_query) = update.callback_query {
+            // a tapped button carries the command it stands for
+            let (Some(message), Some(text)) = (callback_query.message, callback_query.data) else {
+                return Err(Unsupported {
+                    kind: "callback query without data",
+                });
+            };
+            Ok(Self::Text {
+                user: (
+                    callback_query.from.first_name,
+                    callback_query.from.last_name,
+                ),
+                chat: message.chat.id,
+                thread: message
+                    .message_thread_id
+                    .filter(|_| message.is_topic_message),
+                group: matches!(message.chat.kind, ChatType::Group | ChatType::SuperGroup),
+                title: message.chat.title,
+                person: callback_query.from.id,
+                language_code: callback_query.from.language_code,
+                date: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as i64,
+                text,
+                edited: false,
+            })
+        } else if let Some(message) = update.edited_message {
+            // the original command is not undone, the corrected one runs as a new one as of
+            // when the message was first sent, which only makes sense for some commands
+            match message.text.clone() {
+                Some(text) => Ok(Self::from_message(message, text, true)),
+                None => Err(Unsupported {
+                    kind: "edited message without text",
+                }),
             }
+        } else if update.channel_post.is_some() || update.edited_channel_post.is_some() {
+            Err(Unsupported {
+                kind: "channel post",
+            })
         } else {
-            Err(())
+            Err(Unsupported { kind: "unknown" })
         }
     }
 }
+
+#[test]
+fn test_edited_message() {
+    let update: Update = serde_json::from_str(
+        r#"{
+            "update_id": 10001,
+            "edited_message": {
+                "message_id": 1366,
+                "from": {
+                    "id": 1111111,
+                    "is_bot": false,
+                    "first_name": "Eddie"
+                },
+                "chat": {
+                    "id": 1111111,
+                    "first_name": "Eddie",
+                    "type": "private"
+                },
+                "date": 1756400000,
+                "edit_date": 1756400060,
+                "text": "enter 18h30"
+            }
+        }"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        Input::try_from(update),
+        Ok(Input::Text { date: 1756400000, ref text, .. }) if text == "enter 18h30"
+    ));
+
+    let update: Update = serde_json::from_str(r#"{ "update_id": 10002, "poll": {} }"#).unwrap();
+    assert_eq!(
+        Input::try_from(update).err(),
+        Some(Unsupported { kind: "unknown" })
+    );
+}
+
+#[test]
+fn test_supergroup_topic() {
+    let update: Update = serde_json::from_str(
+        r#"{
+            "update_id": 10003,
+            "message": {
+                "message_id": 1367,
+                "from": {
+                    "id": 1111111,
+                    "is_bot": false,
+                    "first_name": "Eddie"
+                },
+                "chat": {
+                    "id": -1001234,
+                    "title": "Atelier",
+                    "type": "supergroup",
+                    "is_forum": true
+                },
+                "date": 1756400000,
+                "message_thread_id": 5,
+                "is_topic_message": true,
+                "text": "enter"
+            }
+        }"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        Input::try_from(update),
+        Ok(Input::Text {
+            chat: -1001234,
+            thread: Some(5),
+            group: true,
+            ..
+        })
+    ));
+}
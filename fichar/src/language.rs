@@ -1,4 +1,6 @@
+use chrono::{DateTime, TimeZone};
 use serde::{Deserialize, Serialize};
+use time_util::{Clock, DateOrder, DateTimeExt, Formatter};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Language {
@@ -7,3 +9,105 @@ pub enum Language {
     #[serde(rename = "es")]
     Es,
 }
+
+impl Language {
+    /// Maps an IETF language tag, as sent by Telegram, to a supported language
+    pub fn from_code(code: &str) -> Self {
+        let primary = code.split(['-', '_']).next().unwrap_or_default();
+        if primary.eq_ignore_ascii_case("es") {
+            Self::Es
+        } else {
+            Self::En
+        }
+    }
+    /// Hour and minute of the instant, on the clock usual to the language
+    pub fn format_time<T: TimeZone>(self, instant: DateTime<T>) -> Formatter<T> {
+        match self {
+            Self::En => instant.format_time(":", Clock::H12),
+            Self::Es => instant.format_time("h", Clock::H24),
+        }
+    }
+    /// Day of the instant, in the order usual to the language
+    pub fn format_date<T: TimeZone>(self, instant: DateTime<T>) -> Formatter<T> {
+        match self {
+            Self::En => instant.format_date("/", DateOrder::Ymd),
+            Self::Es => instant.format_date("/", DateOrder::Dmy),
+        }
+    }
+    /// Name of the month numbered from 1 to 12, `None` outside that range
+    pub fn month_name(self, month: u32) -> Option<&'static str> {
+        let names = match self {
+            Self::En => [
+                "January",
+                "February",
+                "March",
+                "April",
+                "May",
+                "June",
+                "July",
+                "August",
+                "September",
+                "October",
+                "November",
+                "December",
+            ],
+            Self::Es => [
+                "Enero",
+                "Febrero",
+                "Marzo",
+                "Abril",
+                "Mayo",
+                "Junio",
+                "Julio",
+                "Agosto",
+                "Septiembre",
+                "Octubre",
+                "Noviembre",
+                "Diciembre",
+            ],
+        };
+        names.get(month.checked_sub(1)? as usize).copied()
+    }
+}
+
+#[test]
+fn test_from_code() {
+    assert_eq!(Language::from_code("es-ES"), Language::Es);
+    assert_eq!(Language::from_code("es"), Language::Es);
+    assert_eq!(Language::from_code("en-GB"), Language::En);
+    assert_eq!(Language::from_code("fr"), Language::En);
+    assert_eq!(Language::from_code("est"), Language::En);
+    assert_eq!(Language::from_code(""), Language::En);
+}
+
+#[test]
+fn test_month_name() {
+    assert_eq!(Language::En.month_name(8), Some("August"));
+    assert_eq!(Language::Es.month_name(8), Some("Agosto"));
+    assert_eq!(Language::En.month_name(1), Some("January"));
+    assert_eq!(Language::Es.month_name(12), Some("Diciembre"));
+    assert_eq!(Language::En.month_name(0), None);
+    assert_eq!(Language::Es.month_name(13), None);
+}
+
+#[test]
+fn test_format_time() {
+    use chrono::Utc;
+    let instant = Utc
+        .with_ymd_and_hms(2025, 3, 7, 18, 30, 0)
+        .single()
+        .unwrap();
+    assert_eq!(Language::En.format_time(instant).to_string(), "6:30 PM");
+    assert_eq!(Language::Es.format_time(instant).to_string(), "18h30");
+}
+
+#[test]
+fn test_format_date() {
+    use chrono::Utc;
+    let instant = Utc
+        .with_ymd_and_hms(2025, 3, 7, 18, 30, 0)
+        .single()
+        .unwrap();
+    assert_eq!(Language::En.format_date(instant).to_string(), "2025/03/07");
+    assert_eq!(Language::Es.format_date(instant).to_string(), "07/03/2025");
+}
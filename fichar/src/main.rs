@@ -4,23 +4,32 @@ use axum::{
     extract::{Request, State, rejection::JsonRejection},
     http::{HeaderValue, Response, StatusCode},
     middleware::{self, Next},
-    routing::post,
+    routing::{get, post},
 };
 use axum_server::{Handle, tls_rustls::RustlsConfig};
-use chrono::Datelike;
 use clap::{Parser, Subcommand, ValueEnum};
 use fichar::{
     context::Context,
-    input::Input,
+    derive_key,
+    input::{Input, Unsupported},
     language::Language,
-    output::{Output, OutputDaySpan, OutputMonth, TimeFormatter},
-    state::AppState,
+    output::{Localized, Output, OutputMonth, Text},
+    state::{AppState, RateLimit},
 };
-use indoc::{formatdoc, indoc};
 use render::{DocFormat, Renderer};
-use std::collections::HashMap;
-use telegram::Update;
-use time_util::{DateTimeExt, TimeZoneExt};
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
+use telegram::{
+    Bot, ChatAction, InlineKeyboardButton, InlineKeyboardMarkup, TelegramError, Update,
+};
 use tokio::{
     signal,
     sync::mpsc::{self, Receiver, Sender},
@@ -53,7 +62,31 @@ enum Command {
     SetDomain {
         domain: String,
     },
+    /// Longest time, in seconds, changes stay unsaved under continuous activity
+    SetSaveInterval {
+        seconds: u64,
+    },
+    /// Messages a person can send in a row in a chat, and seconds to regain one
+    SetRateLimit {
+        burst: u32,
+        interval: u32,
+    },
     Info,
+    /// Writes the state in a human readable form, readable by its owner only
+    Export {
+        path: PathBuf,
+        /// Keeps the bot token and the webhook secrets in the export
+        #[arg(long)]
+        include_secrets: bool,
+    },
+    /// Replaces the state by the one read from an export
+    Import {
+        path: PathBuf,
+    },
+    /// Checks the state loads and its spans are consistent, without starting the server
+    Validate,
+    /// Prints the event log, one JSON object per line
+    Events,
 }
 impl Default for Command {
     fn default() -> Self {
@@ -86,48 +119,117 @@ async fn main() -> Result<(), Error> {
 
     match command {
         Command::Info => {
-            let state = AppState::load();
+            let state = AppState::load(get_state_key());
             println!("domain: {}", state.hook.domain);
             println!("  port: {}", state.hook.port);
+            println!("  save: every {:?}", state.save_interval);
+            println!(
+                "  rate: {} messages, one more every {}s",
+                state.rate_limit.burst, state.rate_limit.interval
+            );
+        }
+        Command::Export {
+            path,
+            include_secrets,
+        } => {
+            let state = AppState::load(get_state_key());
+            if include_secrets {
+                warn!("the export holds the bot token and the webhook secrets in clear");
+            }
+            write_private(&path, &state.export(include_secrets)).map_err(Error::Io)?;
+        }
+        Command::Import { path } => {
+            let text = std::fs::read_to_string(path).map_err(Error::Io)?;
+            let mut state = AppState::import(&text)
+                .map_err(Error::InvalidExport)?
+                .with_key(get_state_key());
+            if state.hook.is_redacted() {
+                state
+                    .hook
+                    .restore_secrets(AppState::load(get_state_key()).hook);
+            }
+            state.save();
+        }
+        Command::Validate => {
+            let state = AppState::load(get_state_key());
+            let inconsistencies = state.validate();
+            println!("instances: {}", state.instances());
+            for (instance, inconsistency) in &inconsistencies {
+                println!("{instance}: {inconsistency:?}");
+            }
+            if !inconsistencies.is_empty() {
+                return Err(Error::InconsistentState(inconsistencies.len()));
+            }
+            println!("state is consistent");
+        }
+        Command::Events => {
+            for event in AppState::load_events(get_state_key()) {
+                println!("{}", serde_json::to_string(&event).unwrap());
+            }
         }
         Command::SetToken => {
-            let mut state = AppState::load();
+            let mut state = AppState::load(get_state_key());
             state.hook.bot_token = get_token_from_env_var()?;
+            check_token(&state.hook.bot_token).await?;
             state.save();
         }
         Command::SetPort { port } => {
-            let mut state = AppState::load();
+            let mut state = AppState::load(get_state_key());
             state.hook.port = port;
             state.save();
         }
         Command::SetDomain { domain } => {
-            let mut state = AppState::load();
+            let mut state = AppState::load(get_state_key());
             state.hook.domain = domain;
             state.save();
         }
+        Command::SetRateLimit { burst, interval } => {
+            let mut state = AppState::load(get_state_key());
+            state.rate_limit = RateLimit {
+                burst: burst.max(1),
+                interval: interval.max(1),
+            };
+            state.save();
+        }
+        Command::SetSaveInterval { seconds } => {
+            let mut state = AppState::load(get_state_key());
+            state.save_interval = Duration::from_secs(seconds.max(1));
+            state.save();
+        }
         Command::Load { reset_hook } => {
-            let mut state = AppState::load();
+            let mut state = AppState::load(get_state_key());
 
+            // a hook that is not reset is still the one registered by a previous run
+            let webhook_set = Arc::new(AtomicBool::new(!reset_hook));
             if reset_hook {
                 state.hook = state.hook.reset();
-                state.hook.set().await;
+                let hook = state.hook.clone();
+                let webhook_set = webhook_set.clone();
+                tokio::spawn(async move {
+                    hook.set().await;
+                    webhook_set.store(true, Ordering::Relaxed);
+                });
             }
+            let bot = Bot::new(state.hook.bot_token.clone());
+            set_commands(&bot).await;
 
             let hook = state.hook.clone();
 
-            let (i_sender, i_receiver) = mpsc::channel::<Input>(8);
+            let (i_sender, i_receiver) = mpsc::channel::<(u64, Input)>(8);
             let (o_sender, o_receiver) = mpsc::channel::<(Output, Context)>(8);
 
             let processor = tokio::spawn(state.process_inputs(i_receiver, o_sender));
-            let sender = tokio::spawn(sender(hook.bot_token.clone(), o_receiver));
+            let sender = tokio::spawn(sender(bot.clone(), o_receiver));
 
             let app = Router::new()
                 .route("/", post(handler))
-                .with_state(i_sender)
+                .with_state((i_sender, bot))
                 .layer(middleware::from_fn_with_state(
                     HeaderValue::from_str(&hook.secret_token).unwrap(),
                     check_secret_token,
                 ))
+                // probed by orchestrators, which do not know the secret token
+                .route("/healthz", get(health).with_state(webhook_set))
                 .layer(
                     TraceLayer::new_for_http()
                         .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
@@ -146,7 +248,7 @@ async fn main() -> Result<(), Error> {
             termination_signal(handle);
             server.await.unwrap();
 
-            let state = processor.await.unwrap();
+            let mut state = processor.await.unwrap();
             sender.await.unwrap();
 
             info!("graceful shutdown");
@@ -160,39 +262,76 @@ async fn main() -> Result<(), Error> {
                 }
             }
             let bot_token = get_token_from_env_var()?;
+            check_token(&bot_token).await?;
 
-            AppState::new(bot_token, domain, port).save();
+            AppState::new(bot_token, domain, port)
+                .with_key(get_state_key())
+                .save();
         }
     }
     Ok(())
 }
 
 const TOKEN_ENV_VAR: &str = "JUSTMESSAGE_TELEGRAM_BOT_TOKEN";
+const PASSPHRASE_ENV_VAR: &str = "JUSTMESSAGE_STATE_PASSPHRASE";
 
 #[derive(Debug)]
 enum Error {
     TokenEnvVarNotFound,
+    InvalidToken(TelegramError),
+    /// Number of inconsistencies found in the state
+    InconsistentState(usize),
+    Io(std::io::Error),
+    InvalidExport(serde_json::Error),
 }
 
 fn get_token_from_env_var() -> Result<String, Error> {
     std::env::var(TOKEN_ENV_VAR).map_err(|_| Error::TokenEnvVarNotFound)
 }
 
+/// Key encrypting the state on disk, which stays in plaintext without a passphrase
+fn get_state_key() -> Option<[u8; 32]> {
+    std::env::var(PASSPHRASE_ENV_VAR)
+        .ok()
+        .map(|passphrase| derive_key(passphrase.as_bytes()))
+}
+
+/// Refuses a token Telegram does not recognize, before it gets persisted
+async fn check_token(token: &str) -> Result<(), Error> {
+    let me = Bot::new(token)
+        .get_me()
+        .await
+        .map_err(Error::InvalidToken)?;
+    info!("token belongs to bot {:?}", me.username);
+    Ok(())
+}
+
 // async fn printer(payload: String) -> StatusCode {
 //     println!("{payload}");
 //     StatusCode::OK
 // }
 
 async fn handler(
-    sender: State<Sender<Input>>,
+    State((sender, bot)): State<(Sender<(u64, Input)>, Bot)>,
     payload: Result<Json<Update>, JsonRejection>,
 ) -> StatusCode {
     match payload {
         Ok(Json(update)) => {
             // println!("{update:#?}");
-            if let Ok(input) = Input::try_from(update) {
-                // println!("{input:#?}");
-                sender.send(input).await.unwrap();
+            let update_id = update.update_id;
+            let callback_query = update.callback_query.as_ref().map(|query| query.id.clone());
+            match Input::try_from(update) {
+                Ok(input) => {
+                    // println!("{input:#?}");
+                    if sender.send((update_id, input)).await.is_err() {
+                        warn!("input channel closed, dropping update {update_id}");
+                    }
+                }
+                Err(Unsupported { kind }) => info!("ignoring update {update_id}: {kind}"),
+            }
+            // the tapped button shows a spinner until its query is answered
+            if let Some(id) = callback_query {
+                bot.answer_callback_query(id, None).logged().await;
             }
         }
         Err(rejection) => println!("{rejection:#?}"),
@@ -200,24 +339,38 @@ async fn handler(
     StatusCode::OK
 }
 
+#[derive(Debug, Serialize)]
+struct Health {
+    ok: bool,
+    webhook_set: bool,
+}
+
+async fn health(State(webhook_set): State<Arc<AtomicBool>>) -> Json<Health> {
+    Json(Health {
+        ok: true,
+        webhook_set: webhook_set.load(Ordering::Relaxed),
+    })
+}
+
 async fn check_secret_token(
     State(secret_token): State<HeaderValue>,
     request: Request,
     next: Next,
 ) -> Result<Response<Body>, StatusCode> {
     info!("checking secret token");
-    if request
-        .headers()
-        .get("X-Telegram-Bot-Api-Secret-Token")
-        .map(|header| header == secret_token)
-        .unwrap_or(false)
-    {
+    let header = request.headers().get("X-Telegram-Bot-Api-Secret-Token");
+    if secret_token_matches(header, &secret_token) {
         Ok(next.run(request).await)
     } else {
         Err(StatusCode::UNAUTHORIZED)
     }
 }
 
+/// Compares in constant time, so the response delay does not leak how much of the token matched
+fn secret_token_matches(header: Option<&HeaderValue>, secret_token: &HeaderValue) -> bool {
+    header.is_some_and(|header| bool::from(header.as_bytes().ct_eq(secret_token.as_bytes())))
+}
+
 trait Logged {
     async fn logged(self);
 }
@@ -231,298 +384,119 @@ impl<T, E: std::fmt::Debug, F: Future<Output = Result<T, E>>> Logged for F {
     }
 }
 
-async fn sender(token: String, mut receiver: Receiver<(Output, Context)>) {
+/// Publishes the command menu in every supported language
+async fn set_commands(bot: &Bot) {
+    for language in [Language::En, Language::Es] {
+        let commands = match language {
+            Language::En => [
+                ("enter", "Register that you enter now"),
+                ("leave", "Register that you leave now"),
+                ("month", "Summary of the current month"),
+                ("clear", "Clear time spans of today"),
+                ("help", "Examples of available commands"),
+            ],
+            Language::Es => [
+                ("entra", "Registrar que entras ahora"),
+                ("sale", "Registrar que sales ahora"),
+                ("mes", "Resumen del mes actual"),
+                ("borra", "Borrar los tramos de tiempo de hoy"),
+                ("ayuda", "Ejemplos de comandos disponibles"),
+            ],
+        };
+        let commands: Vec<(String, String)> = commands
+            .into_iter()
+            .map(|(command, description)| (command.to_string(), description.to_string()))
+            .collect();
+        let language_code = match language {
+            Language::En => None,
+            Language::Es => Some("es"),
+        };
+        bot.set_my_commands(&commands, language_code).logged().await;
+    }
+}
+
+async fn sender(bot: Bot, mut receiver: Receiver<(Output, Context)>) {
     let renderer = Renderer::new();
     while let Some((output, context)) = receiver.recv().await {
-        match output {
-            Output::Ok => {
-                telegram::send_text(&token, "ok".into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::Failure => {
-                telegram::send_text(&token, "fail".into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::PleasePromoteTheBot => {
-                let text = match context.language {
-                    Language::En => "Please, promote me to administrator in the group settings.",
-                    Language::Es => {
-                        "Por favor, promocioneme administrador en la configuración del grupo."
-                    }
-                };
-                telegram::send_text(&token, text.into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::YourAreNotPartOfAGroup => {
-                let text = match context.language {
-                    Language::En => "You are not part of a group.",
-                    Language::Es => "No eres parte de une grupo.",
-                };
-                telegram::send_text(&token, text.into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::CouldNotRecognizeCommand => {
-                let text = match context.language {
-                    Language::En => "The command you wrote is not recognized.",
-                    Language::Es => "El comando que escribiste no está reconocido.",
-                };
-                telegram::send_text(&token, text.into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::Help => {
-                let text = match context.language {
-                    Language::En => indoc! {"
-                        Here are example of available commands:
-
-                        month
-                        18h30 21h00
-                        enter
-                        enter 18h30
-                        leave
-                        leave 21h00
-                    "},
-                    Language::Es => indoc! {"
-                        Aqui son ejemplos de comandos disponibles:
-
-                        mes
-                        18h30 21h00
-                        entra
-                        entra 18h30
-                        sale
-                        sale 21h00
-                    "},
-                };
-                telegram::send_text(&token, text.into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::SpanHasEarlierLeaveThanEnter(span) => {
-                let enter = context.time_zone.instant(span.enter);
-                let leave = context.time_zone.instant(span.leave);
-                let enter_ymd = enter.format_ymd("/");
-                let leave_ymd = leave.format_ymd("/");
-                let enter_hm = enter.format_hm("h");
-                let leave_hm = leave.format_hm("h");
-
-                let text = match context.language {
-                    Language::En => formatdoc!(
-                        "
-                            The time span has leave instant earlier than enter instant:
-                                - enter {enter_ymd} {enter_hm}
-                                - leave {leave_ymd} {leave_hm}
-                        ",
-                    ),
-                    Language::Es => formatdoc!(
-                        "
-                            El tramo de tiempo tiene instante de salida antes del instante de entrada:
-                                - entra {enter_ymd} {enter_hm}
-                                - sale {leave_ymd} {leave_hm}
-                        ",
-                    ),
-                };
-                telegram::send_text(&token, text, context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::SpanOverrodeSpans(spans) => {
-                use std::fmt::Write;
-                let mut text = String::new();
-                let line = match (context.language, spans.len()) {
-                    (Language::En, 2..) => "The following time spans were overriden:",
-                    (Language::En, ..) => "The following time span was overriden:",
-                    (Language::Es, 2..) => "Se anularon los siguientes tramos de tiempo:",
-                    (Language::Es, ..) => "Se anuló el siguiente tramo de tiempo:",
-                };
-                writeln!(text, "{line}").unwrap();
-                for span in spans {
-                    write!(text, "{}", span.format(&context)).unwrap();
-                }
-                telegram::send_markdown(&token, text, context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::ClearedSpans { spans, day } if spans.is_empty() => {
-                let day = context.time_zone.instant(day).format_ymd("/");
-                let text = match context.language {
-                    Language::En => {
-                        format!("There are no registered time spans on the __{}__.", day)
-                    }
-                    Language::Es => format!("No hay tramo de tiempo registrado el __{}__.", day),
-                };
-                telegram::send_markdown(&token, text, context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::ClearedSpans { spans, day: _ } => {
-                use std::fmt::Write;
-                let mut text = String::new();
-                let line = match (context.language, spans.len()) {
-                    (Language::En, 2..) => "The following time spans were cleared:",
-                    (Language::En, ..) => "The following time span was cleared:",
-                    (Language::Es, 2..) => "Se anularon los siguientes tramos de tiempo:",
-                    (Language::Es, ..) => "Se anuló el siguiente tramo de tiempo:",
+        match (output.text(&context), output) {
+            (Text::Markdown(text), Output::ConfirmClear { .. }) => {
+                let (button, command) = match context.language {
+                    Language::En => ("Confirm", "confirm clear"),
+                    Language::Es => ("Confirmar", "confirma borra"),
                 };
-                writeln!(text, "{line}").unwrap();
-                for span in spans {
-                    write!(text, "{}", span.format(&context)).unwrap();
-                }
-                telegram::send_markdown(&token, text, context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::CouldNotInferMinute => {
-                let text = match context.language {
-                    Language::En => {
-                        "I was not able to determine the time based on your indication."
-                    }
-                    Language::Es => {
-                        "No era capaz de determinar el tiempo basandome en tu indicación."
-                    }
+                let markup = InlineKeyboardMarkup {
+                    inline_keyboard: vec![vec![InlineKeyboardButton::callback(button, command)]],
                 };
-                telegram::send_text(&token, text.into(), context.chat)
+                bot.send_markdown_with_keyboard(text, context.chat, markup, context.thread)
                     .logged()
                     .await;
             }
-            Output::CouldNotInferDay => {
-                let text = match context.language {
-                    Language::En => {
-                        "I was not able to determine the date based on your indication."
-                    }
-                    Language::Es => {
-                        "No era capaz de determinar la fecha basandome en tu indicación."
-                    }
-                };
-                telegram::send_text(&token, text.into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::CouldNotInferMonth => {
-                let text = match context.language {
-                    Language::En => {
-                        "I was not able to determine the month based on your indication."
-                    }
-                    Language::Es => "No era capaz de determinar el mes basandome en tu indicación.",
-                };
-                telegram::send_text(&token, text.into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::EnterOverrodeEntered(enter) => {
-                let text = match context.language {
-                    Language::En => "The previous entering time was overriden:",
-                    Language::Es => "La hora de entrada previa se anuló:",
+            (
+                text,
+                Output::Month {
+                    person: _,
+                    format,
+                    month,
+                    week,
+                    spans,
+                    absences,
+                    name,
+                    contracted_minutes,
+                    time_zone,
+                },
+            ) => {
+                let shown = Context {
+                    time_zone,
+                    ..context
                 };
-                let enter = TimeFormatter::new(enter, &context);
-                let text = format!("{text}\n{enter}");
-                telegram::send_markdown(&token, text, context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::TryLeaveButNotEntered => {
-                let text = match context.language {
-                    Language::En => {
-                        "You are trying to leave, but you did not enter in the first place."
-                    }
-                    Language::Es => "Estás tratando de salir, pero no entraste en primer lugar.",
+                let month = OutputMonth::new(&shown, name, month, week, spans, contracted_minutes)
+                    .with_absences(&shown, absences);
+
+                let action = match format {
+                    DocFormat::Png => ChatAction::UploadPhoto,
+                    DocFormat::Pdf => ChatAction::UploadDocument,
                 };
-                telegram::send_text(&token, text.into(), context.chat)
+                bot.send_chat_action(context.chat, action, context.thread)
                     .logged()
                     .await;
-            }
-            Output::Month {
-                person: _,
-                format,
-                month,
-                spans,
-                name,
-            } => {
-                let month = context.time_zone.instant(month);
-
-                let mut month = OutputMonth {
-                    language: context.language,
-                    name,
-                    year: month.year(),
-                    month: month.month(),
-                    spans: Vec::new(),
-                    minutes: 0,
-                };
-                for span in spans {
-                    let enter = context.time_zone.instant(span.enter);
-                    let leave = context.time_zone.instant(span.leave);
-                    month.spans.push(OutputDaySpan {
-                        date: enter.into(),
-                        enter: enter.into(),
-                        leave: leave.into(),
-                        minutes: span.minutes(),
-                    });
-                    month.minutes += span.minutes();
-                }
 
-                let document = renderer.render(
-                    include_str!("month.typ"),
-                    HashMap::new(),
-                    HashMap::from([(
-                        "month.json",
-                        serde_json::to_string_pretty(&month).unwrap().into_bytes(),
-                    )]),
-                    format,
-                );
-                if let Ok(document) = document {
-                    match format {
-                        DocFormat::Png => {
-                            telegram::send_photo(&token, document, context.chat)
-                                .logged()
-                                .await
-                        }
-                        DocFormat::Pdf => {
-                            telegram::send_document(&token, document, context.chat)
-                                .logged()
-                                .await
+                match month.render(&renderer, include_str!("month.typ"), format) {
+                    Ok(documents) => {
+                        send(&bot, text, &context).await;
+                        match format {
+                            DocFormat::Png => {
+                                bot.send_media_group(context.chat, documents, context.thread)
+                                    .logged()
+                                    .await
+                            }
+                            DocFormat::Pdf => {
+                                for document in documents {
+                                    bot.send_document(document, context.chat, context.thread)
+                                        .logged()
+                                        .await
+                                }
+                            }
                         }
                     }
-                } else {
-                    warn!("fail to generate document");
+                    Err(failure) => send(&bot, failure.text(&context), &context).await,
                 }
             }
-            Output::IAmNowAdministrator => {
-                let text = match context.language {
-                    Language::En => {
-                        "I am now administrator in the group. I can now see messages published in the group and respond to them."
-                    }
-                    Language::Es => {
-                        "Ahora soy administrador en el grupo. Ahora puedo ver los mensages publicados en el grupo y contestarlos."
-                    }
-                };
-                telegram::send_text(&token, text.into(), context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::SpanAdded(span) => {
-                let text = match context.language {
-                    Language::En => "Time span registered:",
-                    Language::Es => "Tramo de tiempo registrado:",
-                };
-                let text = format!("{}\n{}", text, span.format(&context));
-                telegram::send_markdown(&token, text, context.chat)
-                    .logged()
-                    .await;
-            }
-            Output::Entered(enter) => {
-                let text = match context.language {
-                    Language::En => "You enter:",
-                    Language::Es => "Entras:",
-                };
-                let enter = TimeFormatter::new(enter, &context);
-                let text = format!("{text}\n{enter}");
-                telegram::send_markdown(&token, text, context.chat)
-                    .logged()
-                    .await;
-            }
+            (text, _) => send(&bot, text, &context).await,
+        }
+    }
+}
+
+async fn send(bot: &Bot, text: Text, context: &Context) {
+    match text {
+        Text::Plain(text) => {
+            bot.send_text(text, context.chat, context.thread)
+                .logged()
+                .await
+        }
+        Text::Markdown(text) => {
+            bot.send_markdown(text, context.chat, context.thread)
+                .logged()
+                .await
         }
     }
 }
@@ -559,3 +533,71 @@ fn termination_signal(handle: Handle) {
         handle.graceful_shutdown(None);
     });
 }
+
+/// Writes a file only its owner can read, even when it already existed
+fn write_private(path: &Path, text: &str) -> std::io::Result<()> {
+    use std::{
+        fs::{OpenOptions, Permissions},
+        io::Write,
+        os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    };
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(text.as_bytes())
+}
+
+#[test]
+fn test_health() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let webhook_set = Arc::new(AtomicBool::new(false));
+    let Json(body) = runtime.block_on(health(State(webhook_set.clone())));
+    assert_eq!(
+        serde_json::to_string(&body).unwrap(),
+        r#"{"ok":true,"webhook_set":false}"#
+    );
+    webhook_set.store(true, Ordering::Relaxed);
+    let Json(body) = runtime.block_on(health(State(webhook_set)));
+    assert_eq!(
+        serde_json::to_string(&body).unwrap(),
+        r#"{"ok":true,"webhook_set":true}"#
+    );
+}
+
+#[test]
+fn test_secret_token_matches() {
+    let secret_token = HeaderValue::from_static("s3cr3t-t0k3n");
+    let header = |value| HeaderValue::from_static(value);
+    assert!(secret_token_matches(
+        Some(&header("s3cr3t-t0k3n")),
+        &secret_token
+    ));
+    assert!(!secret_token_matches(
+        Some(&header("s3cr3t-t0k3m")),
+        &secret_token
+    ));
+    assert!(!secret_token_matches(
+        Some(&header("s3cr3t")),
+        &secret_token
+    ));
+    assert!(!secret_token_matches(Some(&header("")), &secret_token));
+    assert!(!secret_token_matches(None, &secret_token));
+}
+
+#[test]
+fn test_write_private() {
+    use std::os::unix::fs::PermissionsExt;
+    let path = std::env::temp_dir().join(format!("fichar-export-{}.json", std::process::id()));
+    std::fs::write(&path, "previous content, readable by all").unwrap();
+    write_private(&path, "{}").unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}");
+    std::fs::remove_file(path).unwrap();
+}
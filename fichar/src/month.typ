@@ -1,36 +1,6 @@
 #import "@preview/oxifmt:1.0.0": strfmt
 #set page(width: auto, height: auto, margin: 1cm)
 
-#let MONTHS = (
-  en: (
-    [January],
-    [February],
-    [Mars],
-    [April],
-    [May],
-    [June],
-    [Jully],
-    [August],
-    [September],
-    [October],
-    [November],
-    [December],
-  ),
-  es: (
-    [Enero],
-    [Febrero],
-    [Marso],
-    [Abril],
-    [Mayo],
-    [Junio],
-    [Julio],
-    [Agosto],
-    [Septiembre],
-    [Octubro],
-    [Noviembre],
-    [Diciembre],
-  )
-);
 #let WORDS = (
   en: (
     date: [date],
@@ -38,6 +8,12 @@
     leave: [leave],
     duration: [duration],
     total: [Total],
+    week: [Week],
+    overtime: [Overtime],
+    absences: [Absences],
+    holiday: [holiday],
+    sick: [sick leave],
+    vacation: [vacation],
   ),
   es: (
     date: [fecha],
@@ -45,11 +21,16 @@
     leave: [sale],
     duration: [duración],
     total: [Total],
+    week: [Semana],
+    overtime: [Horas extra],
+    absences: [Ausencias],
+    holiday: [festivo],
+    sick: [baja por enfermedad],
+    vacation: [vacaciones],
   ),
 )
 
 #let infos = json("month.json")
-#let MONTHS = MONTHS.at(infos.language)
 #let WORDS = WORDS.at(infos.language)
 
 #let fmt-date((year, month, day)) = {
@@ -61,9 +42,6 @@
 #let fmt-duration((hours, minutes)) = {
   strfmt("{}h{:0>2}m", hours, minutes)
 }
-#let fmt-month(month) = {
-  MONTHS.at(month - 1)
-}
 
 
 #let hours-from-minutes(minutes) = {
@@ -72,22 +50,45 @@
   (hours: hours, minutes: minutes)
 }
 
-= #infos.year #fmt-month(infos.month)
+#if infos.week == none [
+  = #infos.year #infos.month_name
+] else [
+  = #infos.year #WORDS.week #infos.week
+]
 
 == #infos.name
 
-#table(columns: 4, align: (left, right, right, right),
-  table.header(WORDS.date, WORDS.enter, WORDS.leave, WORDS.duration),
-  .. infos.spans.map(
-    span => (
-      fmt-date(span.date),
-      fmt-time(span.enter),
-      fmt-time(span.leave),
-      fmt-duration(hours-from-minutes(span.minutes))
-    ),
-  ).flatten()
-)
+#let pages = infos.spans.chunks(infos.rows_per_page)
+#if pages.len() == 0 { pages = ((),) }
+#for (i, page) in pages.enumerate() {
+  if i > 0 { pagebreak() }
+  table(columns: 4, align: (left, right, right, right),
+    table.header(WORDS.date, WORDS.enter, WORDS.leave, WORDS.duration),
+    .. page.map(
+      span => (
+        fmt-date(span.date),
+        fmt-time(span.enter),
+        fmt-time(span.leave),
+        fmt-duration(hours-from-minutes(span.minutes))
+      ),
+    ).flatten()
+  )
+}
+
+#if infos.absences.len() > 0 [
+  #WORDS.absences:
+  #list(.. infos.absences.map(
+    absence => [#fmt-date(absence.date): #WORDS.at(absence.kind)]
+  ))
+]
 
 #let total = hours-from-minutes(infos.minutes)
 
 #WORDS.total: #fmt-duration(total)
+
+#if infos.overtime_minutes != none [
+  \
+  #text(fill: if infos.overtime_minutes > 0 { red } else { black })[
+    #WORDS.overtime: #fmt-duration(hours-from-minutes(infos.overtime_minutes))
+  ]
+]
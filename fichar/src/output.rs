@@ -1,10 +1,20 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write},
+};
 
-use crate::{context::Context, language::Language, state::instance::Span};
-use chrono::{DateTime, Datelike, TimeZone, Timelike};
-use render::DocFormat;
+use crate::{
+    context::Context,
+    language::Language,
+    state::instance::{AbsenceKind, Span},
+};
+use chrono::{DateTime, Datelike, Days, TimeZone, Timelike};
+use chrono_tz::Tz;
+use indoc::{formatdoc, indoc};
+use render::{DocFormat, Renderer};
 use serde::Serialize;
 use time_util::{DateTimeExt, TimeZoneExt};
+use tracing::warn;
 
 #[derive(Debug, Clone)]
 pub enum Output {
@@ -14,6 +24,38 @@ pub enum Output {
     YourAreNotPartOfAGroup,
     CouldNotRecognizeCommand,
     Help,
+    Status {
+        entered_since: Option<i64>,
+        today_minutes: u32,
+    },
+    DaySummary {
+        spans: Vec<Span>,
+        /// Open entry, up to the time of the message
+        open: Option<Span>,
+    },
+    /// Time zones by the name of their city in the language of the chat
+    TimeZones(Vec<(&'static str, Tz)>),
+    /// Persons of the group with their name, in the order targets index them
+    Persons(Vec<(i64, String)>),
+    PersonRemoved {
+        person: i64,
+        name: String,
+    },
+    /// The administrator right of the person was given or taken
+    AdminSet {
+        person: i64,
+        name: String,
+        admin: bool,
+    },
+    PermissionDenied,
+    UnknownPerson,
+    UnknownGroup,
+    /// Time zone name as written by the person
+    UnknownTimeZone(String),
+    /// The edit of a message is not taken into account for its command
+    EditIgnored,
+    /// Sent once when a chat exceeds its rate limit, further messages are dropped silently
+    SlowDown,
     SpanAdded(Span),
     Entered(i64),
     SpanHasEarlierLeaveThanEnter(Span),
@@ -22,29 +64,425 @@ pub enum Output {
         day: i64,
         spans: Vec<Span>,
     },
+    ConfirmClear {
+        day: i64,
+        spans: Vec<Span>,
+    },
+    NothingToConfirm,
+    AbsenceMarked {
+        day: i64,
+        kind: AbsenceKind,
+    },
     EnterOverrodeEntered(i64),
+    /// Recorded times that were rounded, from the given one to the stored one
+    Rounded(Vec<(i64, i64)>),
+    /// Entry of the person closed on their behalf, not always the one who sent the command
+    AutoClosedEntry {
+        person: i64,
+        span: Span,
+    },
     TryLeaveButNotEntered,
     CouldNotInferMinute,
     CouldNotInferDay,
+    CouldNotInferWeek,
     CouldNotInferMonth,
+    /// More contracted hours than a month holds
+    TooManyHours,
     Month {
         format: DocFormat,
         person: i64,
         name: String,
         month: i64,
+        /// The document covers the week starting at `month` instead of the month
+        week: bool,
         spans: Vec<Span>,
+        absences: Vec<(i64, AbsenceKind)>,
+        contracted_minutes: Option<u32>,
+        /// Zone the document is shown in, which may differ from the one of the instance
+        time_zone: Tz,
     },
+    CouldNotGenerateDocument,
     IAmNowAdministrator,
 }
 
+/// Message sent to the chat, markdown is already escaped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Text {
+    Plain(String),
+    Markdown(String),
+}
+
+/// Text of a message in the language of the chat
+///
+/// Matching exhaustively, the compiler forces every new output to be given a text.
+pub trait Localized {
+    fn text(&self, context: &Context) -> Text;
+}
+
+impl Localized for Output {
+    fn text(&self, context: &Context) -> Text {
+        let language = context.language;
+        let plain = |en: &str, es: &str| {
+            Text::Plain(match language {
+                Language::En => en.to_string(),
+                Language::Es => es.to_string(),
+            })
+        };
+        let spans = |line: &str, spans: &[Span]| {
+            let mut text = String::new();
+            writeln!(text, "{line}").unwrap();
+            for span in spans {
+                write!(text, "{}", span.format(context)).unwrap();
+            }
+            Text::Markdown(text)
+        };
+        match self {
+            Self::Ok => Text::Plain("ok".into()),
+            Self::Failure => Text::Plain("fail".into()),
+            Self::PleasePromoteTheBot => plain(
+                "Please, promote me to administrator in the group settings.",
+                "Por favor, promocioneme administrador en la configuración del grupo.",
+            ),
+            Self::YourAreNotPartOfAGroup => plain(
+                "You are not part of a group.",
+                "No eres parte de une grupo.",
+            ),
+            Self::CouldNotRecognizeCommand => plain(
+                "The command you wrote is not recognized.",
+                "El comando que escribiste no está reconocido.",
+            ),
+            Self::Help => plain(
+                indoc! {"
+                    Here are example of available commands:
+
+                    month
+                    week
+                    18h30 21h00
+                    enter
+                    enter 18h30
+                    leave
+                    leave 21h00
+                "},
+                indoc! {"
+                    Aqui son ejemplos de comandos disponibles:
+
+                    mes
+                    semana
+                    18h30 21h00
+                    entra
+                    entra 18h30
+                    sale
+                    sale 21h00
+                "},
+            ),
+            Self::Status {
+                entered_since,
+                today_minutes,
+            } => {
+                let mut text = String::new();
+                match (language, entered_since) {
+                    (Language::En, Some(_)) => writeln!(text, "You are in since:"),
+                    (Language::Es, Some(_)) => writeln!(text, "Estás dentro desde:"),
+                    (Language::En, None) => writeln!(text, "You are not in\\."),
+                    (Language::Es, None) => writeln!(text, "No estás dentro\\."),
+                }
+                .unwrap();
+                if let Some(enter) = entered_since {
+                    writeln!(text, "{}", TimeFormatter::new(*enter, context)).unwrap();
+                }
+                let hours = today_minutes.div_euclid(60);
+                let minutes = today_minutes.rem_euclid(60);
+                let today = match language {
+                    Language::En => "Today",
+                    Language::Es => "Hoy",
+                };
+                write!(text, "{today}: _{hours}h{minutes:0>2}_").unwrap();
+                Text::Markdown(text)
+            }
+            Self::DaySummary { spans, open } => {
+                let mut text = String::from(match language {
+                    Language::En => "Today so far:\n",
+                    Language::Es => "Hoy por ahora:\n",
+                });
+                for span in spans {
+                    write!(text, "{}", span.format(context)).unwrap();
+                }
+                if let Some(open) = open {
+                    let since = match language {
+                        Language::En => "You are in since:",
+                        Language::Es => "Estás dentro desde:",
+                    };
+                    let minutes = open.minutes();
+                    let (hours, minutes) = (minutes.div_euclid(60), minutes.rem_euclid(60));
+                    writeln!(text, "{since}").unwrap();
+                    writeln!(
+                        text,
+                        "{} \\(_{hours}h{minutes:0>2}_\\)",
+                        TimeFormatter::new(open.enter, context)
+                    )
+                    .unwrap();
+                }
+                let minutes: u32 = spans.iter().chain(open).map(|span| span.minutes()).sum();
+                let (hours, minutes) = (minutes.div_euclid(60), minutes.rem_euclid(60));
+                write!(text, "Total: _{hours}h{minutes:0>2}_").unwrap();
+                Text::Markdown(text)
+            }
+            Self::Persons(persons) => {
+                let mut text = String::from(match language {
+                    Language::En => "Persons of the group:\n",
+                    Language::Es => "Personas del grupo:\n",
+                });
+                for (index, (_, name)) in persons.iter().enumerate() {
+                    writeln!(text, "@{} {}", index + 1, name).unwrap();
+                }
+                Text::Plain(text)
+            }
+            Self::PersonRemoved { name, .. } => Text::Plain(match language {
+                Language::En => format!("{name} was removed from the group."),
+                Language::Es => format!("{name} fue quitado del grupo."),
+            }),
+            Self::AdminSet { name, admin, .. } => Text::Plain(match (language, admin) {
+                (Language::En, true) => format!("{name} is now an administrator."),
+                (Language::En, false) => format!("{name} is no longer an administrator."),
+                (Language::Es, true) => format!("{name} ahora es administrador."),
+                (Language::Es, false) => format!("{name} ya no es administrador."),
+            }),
+            Self::PermissionDenied => plain(
+                "Only an administrator of the group can do this.",
+                "Solo un administrador del grupo puede hacer esto.",
+            ),
+            Self::UnknownPerson => plain(
+                "There is no such person in the group.",
+                "No hay tal persona en el grupo.",
+            ),
+            Self::UnknownGroup => plain(
+                "You are not part of a group with that name.",
+                "No formas parte de un grupo con ese nombre.",
+            ),
+            Self::TimeZones(time_zones) => {
+                let mut text = String::from(match language {
+                    Language::En => "Time zones you can give by their city:",
+                    Language::Es => "Zonas horarias que puedes indicar por su ciudad:",
+                });
+                for (city, time_zone) in time_zones {
+                    write!(text, "\n- {city} ({time_zone})").unwrap();
+                }
+                Text::Plain(text)
+            }
+            Self::UnknownTimeZone(time_zone) => {
+                let mut text = match language {
+                    Language::En => format!("The time zone {time_zone} is not known."),
+                    Language::Es => format!("La zona horaria {time_zone} no es conocida."),
+                };
+                if let Some(nearest) = nearest_time_zone(time_zone) {
+                    match language {
+                        Language::En => write!(text, " Did you mean {nearest}?"),
+                        Language::Es => write!(text, " ¿Quisiste decir {nearest}?"),
+                    }
+                    .unwrap();
+                }
+                Text::Plain(text)
+            }
+            Self::EditIgnored => plain(
+                "Only the correction of an enter or of a time span is taken into account, send the corrected command as a new message.",
+                "Solo se tiene en cuenta la corrección de una entrada o de un tramo de tiempo, envía el comando corregido en un nuevo mensaje.",
+            ),
+            Self::SlowDown => plain(
+                "Too many messages, please slow down.",
+                "Demasiados mensajes, por favor ve más despacio.",
+            ),
+            Self::SpanHasEarlierLeaveThanEnter(span) => {
+                let enter = context.time_zone.instant(span.enter);
+                let leave = context.time_zone.instant(span.leave);
+                let enter_ymd = language.format_date(enter);
+                let leave_ymd = language.format_date(leave);
+                let enter_hm = language.format_time(enter);
+                let leave_hm = language.format_time(leave);
+
+                Text::Plain(match language {
+                    Language::En => formatdoc!(
+                        "
+                            The time span has leave instant earlier than enter instant:
+                                - enter {enter_ymd} {enter_hm}
+                                - leave {leave_ymd} {leave_hm}
+                        ",
+                    ),
+                    Language::Es => formatdoc!(
+                        "
+                            El tramo de tiempo tiene instante de salida antes del instante de entrada:
+                                - entra {enter_ymd} {enter_hm}
+                                - sale {leave_ymd} {leave_hm}
+                        ",
+                    ),
+                })
+            }
+            Self::SpanOverrodeSpans(overriden) => {
+                let line = match (language, overriden.len()) {
+                    (Language::En, 2..) => "The following time spans were overriden:",
+                    (Language::En, ..) => "The following time span was overriden:",
+                    (Language::Es, 2..) => "Se anularon los siguientes tramos de tiempo:",
+                    (Language::Es, ..) => "Se anuló el siguiente tramo de tiempo:",
+                };
+                spans(line, overriden)
+            }
+            Self::ClearedSpans {
+                spans: cleared,
+                day,
+            } if cleared.is_empty() => {
+                let day = language.format_date(context.time_zone.instant(*day));
+                Text::Markdown(match language {
+                    Language::En => {
+                        format!("There are no registered time spans on the __{}__\\.", day)
+                    }
+                    Language::Es => format!("No hay tramo de tiempo registrado el __{}__\\.", day),
+                })
+            }
+            Self::ClearedSpans {
+                spans: cleared,
+                day: _,
+            } => {
+                let line = match (language, cleared.len()) {
+                    (Language::En, 2..) => "The following time spans were cleared:",
+                    (Language::En, ..) => "The following time span was cleared:",
+                    (Language::Es, 2..) => "Se anularon los siguientes tramos de tiempo:",
+                    (Language::Es, ..) => "Se anuló el siguiente tramo de tiempo:",
+                };
+                spans(line, cleared)
+            }
+            Self::ConfirmClear {
+                spans: pending,
+                day: _,
+            } => {
+                let line = match (language, pending.len()) {
+                    (Language::En, 2..) => "The following time spans will be cleared:",
+                    (Language::En, ..) => "The following time span will be cleared:",
+                    (Language::Es, 2..) => "Se borrarán los siguientes tramos de tiempo:",
+                    (Language::Es, ..) => "Se borrará el siguiente tramo de tiempo:",
+                };
+                spans(line, pending)
+            }
+            Self::NothingToConfirm => plain(
+                "There is nothing waiting for confirmation.",
+                "No hay nada pendiente de confirmación.",
+            ),
+            Self::AbsenceMarked { day, kind } => {
+                let day = language.format_date(context.time_zone.instant(*day));
+                let kind = match (language, kind) {
+                    (Language::En, AbsenceKind::Holiday) => "holiday",
+                    (Language::En, AbsenceKind::Sick) => "sick leave",
+                    (Language::En, AbsenceKind::Vacation) => "vacation",
+                    (Language::Es, AbsenceKind::Holiday) => "festivo",
+                    (Language::Es, AbsenceKind::Sick) => "baja por enfermedad",
+                    (Language::Es, AbsenceKind::Vacation) => "vacaciones",
+                };
+                Text::Markdown(match language {
+                    Language::En => format!("The __{day}__ is marked as _{kind}_\\."),
+                    Language::Es => format!("El __{day}__ queda marcado como _{kind}_\\."),
+                })
+            }
+            Self::CouldNotInferMinute => plain(
+                "I was not able to determine the time based on your indication.",
+                "No era capaz de determinar el tiempo basandome en tu indicación.",
+            ),
+            Self::CouldNotInferDay => plain(
+                "I was not able to determine the date based on your indication.",
+                "No era capaz de determinar la fecha basandome en tu indicación.",
+            ),
+            Self::CouldNotInferWeek => plain(
+                "I was not able to determine the week based on your indication.",
+                "No era capaz de determinar la semana basandome en tu indicación.",
+            ),
+            Self::CouldNotInferMonth => plain(
+                "I was not able to determine the month based on your indication.",
+                "No era capaz de determinar el mes basandome en tu indicación.",
+            ),
+            Self::TooManyHours => plain(
+                "A month does not hold that many hours.",
+                "Un mes no tiene tantas horas.",
+            ),
+            Self::CouldNotGenerateDocument => plain(
+                "I couldn't generate the report, try again.",
+                "No pude generar el informe, inténtalo de nuevo.",
+            ),
+            Self::EnterOverrodeEntered(enter) => {
+                let text = match language {
+                    Language::En => "The previous entering time was overriden:",
+                    Language::Es => "La hora de entrada previa se anuló:",
+                };
+                let enter = TimeFormatter::new(*enter, context);
+                Text::Markdown(format!("{text}\n{enter}"))
+            }
+            Self::AutoClosedEntry { span, .. } => {
+                let text = match language {
+                    Language::En => "An entry left open for too long was closed automatically:",
+                    Language::Es => {
+                        "Una entrada abierta durante demasiado tiempo se cerró automáticamente:"
+                    }
+                };
+                Text::Markdown(format!("{}\n{}", text, span.format(context)))
+            }
+            Self::TryLeaveButNotEntered => plain(
+                "You are trying to leave, but you did not enter in the first place.",
+                "Estás tratando de salir, pero no entraste en primer lugar.",
+            ),
+            // announces the document that follows
+            Self::Month { name, .. } => Text::Plain(match language {
+                Language::En => format!("Time sheet of {name}:"),
+                Language::Es => format!("Registro horario de {name}:"),
+            }),
+            Self::IAmNowAdministrator => plain(
+                "I am now administrator in the group. I can now see messages published in the group and respond to them.",
+                "Ahora soy administrador en el grupo. Ahora puedo ver los mensages publicados en el grupo y contestarlos.",
+            ),
+            Self::SpanAdded(span) => {
+                let text = match language {
+                    Language::En => "Time span registered:",
+                    Language::Es => "Tramo de tiempo registrado:",
+                };
+                Text::Markdown(format!("{}\n{}", text, span.format(context)))
+            }
+            Self::Rounded(rounded) => {
+                let mut text = String::from(match language {
+                    Language::En => "Times were rounded:",
+                    Language::Es => "Se redondearon las horas:",
+                });
+                for (raw, stored) in rounded {
+                    let raw = language.format_time(context.time_zone.instant(*raw));
+                    let stored = TimeFormatter::new(*stored, context);
+                    write!(text, "\n{stored} \\({raw}\\)").unwrap();
+                }
+                Text::Markdown(text)
+            }
+            Self::Entered(enter) => {
+                let text = match language {
+                    Language::En => "You enter:",
+                    Language::Es => "Entras:",
+                };
+                let enter = TimeFormatter::new(*enter, context);
+                Text::Markdown(format!("{text}\n{enter}"))
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct OutputMonth {
     pub language: Language,
     pub name: String,
     pub year: i32,
     pub month: u32,
+    /// Localized name of the month
+    pub month_name: &'static str,
+    /// ISO week number, when the document covers a single week
+    pub week: Option<u32>,
     pub spans: Vec<OutputDaySpan>,
+    /// Spans listed on each page before breaking to the next one
+    pub rows_per_page: usize,
+    /// Days off, not counted in the minutes
+    pub absences: Vec<OutputAbsence>,
     pub minutes: u32,
+    /// Minutes worked beyond the contracted ones, when a monthly contract is set
+    pub overtime_minutes: Option<u32>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize)]
@@ -55,6 +493,12 @@ pub struct OutputDaySpan {
     pub minutes: u32,
 }
 
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct OutputAbsence {
+    pub date: OutputDate,
+    pub kind: AbsenceKind,
+}
+
 #[derive(Debug, Clone, Copy, Serialize)]
 pub struct OutputDate {
     pub year: i32,
@@ -68,6 +512,92 @@ pub struct OutputTime {
     pub minute: u32,
 }
 
+/// Enough for a month with a single span per working day
+pub const ROWS_PER_PAGE: usize = 31;
+
+impl OutputMonth {
+    pub fn new(
+        context: &Context,
+        name: String,
+        start: i64,
+        week: bool,
+        spans: Vec<Span>,
+        contracted_minutes: Option<u32>,
+    ) -> Self {
+        let start = context.time_zone.instant(start);
+        // midweek, weeks starting on sunday are numbered after the ISO week they mostly overlap
+        let iso_week = (start + Days::new(3)).iso_week();
+        let mut month = Self {
+            language: context.language,
+            name,
+            year: if week { iso_week.year() } else { start.year() },
+            month: start.month(),
+            month_name: context
+                .language
+                .month_name(start.month())
+                .expect("months are numbered from 1 to 12"),
+            week: week.then_some(iso_week.week()),
+            spans: Vec::new(),
+            rows_per_page: ROWS_PER_PAGE,
+            absences: Vec::new(),
+            minutes: 0,
+            overtime_minutes: None,
+        };
+        for span in spans {
+            let enter = context.time_zone.instant(span.enter);
+            let leave = context.time_zone.instant(span.leave);
+            month.spans.push(OutputDaySpan {
+                date: enter.into(),
+                enter: enter.into(),
+                leave: leave.into(),
+                minutes: span.minutes(),
+            });
+            month.minutes += span.minutes();
+        }
+        // the contract is monthly, it does not apply to a single week
+        if !week {
+            month.overtime_minutes =
+                contracted_minutes.map(|contracted| month.minutes.saturating_sub(contracted));
+        }
+        month
+    }
+    pub fn with_absences(mut self, context: &Context, absences: Vec<(i64, AbsenceKind)>) -> Self {
+        self.absences = absences
+            .into_iter()
+            .map(|(day, kind)| OutputAbsence {
+                date: context.time_zone.instant(day).into(),
+                kind,
+            })
+            .collect();
+        self
+    }
+
+    /// Renders the document, retrying once, or gives the message telling the chat it failed
+    pub fn render(
+        &self,
+        renderer: &Renderer,
+        template: &str,
+        format: DocFormat,
+    ) -> Result<Vec<Vec<u8>>, Output> {
+        let json = serde_json::to_string_pretty(self).unwrap().into_bytes();
+        for attempt in 1..=2 {
+            let bytes = HashMap::from([("month.json", json.clone())]);
+            // images are sent one per page, a pdf holds all its pages
+            let result = match format {
+                DocFormat::Png => renderer.render_pages(template, HashMap::new(), bytes),
+                DocFormat::Pdf => renderer
+                    .render(template, HashMap::new(), bytes, format)
+                    .map(|document| vec![document]),
+            };
+            match result {
+                Ok(documents) => return Ok(documents),
+                Err(err) => warn!("fail to generate document (attempt {attempt}): {err:?}"),
+            }
+        }
+        Err(Output::CouldNotGenerateDocument)
+    }
+}
+
 impl<T: TimeZone> From<DateTime<T>> for OutputDate {
     fn from(date: DateTime<T>) -> Self {
         Self {
@@ -106,9 +636,9 @@ impl<'a> Display for SpanFormatter<'a> {
             (Language::Es, 2..) => "a las",
         };
 
-        let date = enter.format_ymd("/");
-        let enter = enter.format_hm("h");
-        let leave = leave.format_hm("h");
+        let date = self.context.language.format_date(enter);
+        let enter = self.context.language.format_time(enter);
+        let leave = self.context.language.format_time(leave);
 
         let minutes = self.span.minutes();
         let hours = minutes.div_euclid(60);
@@ -128,6 +658,50 @@ impl Span {
         }
     }
 }
+/// Closest time zone name, either in full or only the part after the last slash, when
+/// it is a few typos away
+fn nearest_time_zone(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    chrono_tz::TZ_VARIANTS
+        .iter()
+        .map(|time_zone| {
+            let full = time_zone.name();
+            let city = full.rsplit('/').next().unwrap_or(full);
+            let distance = edit_distance(&name, &full.to_lowercase())
+                .min(edit_distance(&name, &city.to_lowercase()));
+            (distance, full)
+        })
+        .min_by_key(|&(distance, _)| distance)
+        .filter(|&(distance, _)| distance <= 2.max(name.chars().count() / 4))
+        .map(|(_, full)| full)
+}
+
+/// Edits between the two strings, a swap of adjacent characters counting as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
 pub struct TimeFormatter<'a> {
     pub context: &'a Context,
     pub time: i64,
@@ -145,8 +719,275 @@ impl<'a> Display for TimeFormatter<'a> {
             (Language::Es, 0..=1) => "a la",
             (Language::Es, 2..) => "a las",
         };
-        let date = time.format_ymd("/");
-        let time = time.format_hm("h");
+        let date = self.context.language.format_date(time);
+        let time = self.context.language.format_time(time);
         write!(f, "▸ __{date}__ {at} {time}")
     }
 }
+
+#[test]
+fn test_overtime() {
+    use chrono_tz::Tz;
+    let context = Context {
+        chat: 0,
+        thread: None,
+        date: 0,
+        language: Language::En,
+        time_zone: Tz::UTC,
+    };
+    // 2023-11-01 00:00:00 UTC
+    let start = 1_698_796_800;
+    let spans = Vec::from([
+        Span {
+            enter: start + 9 * 60 * 60,
+            leave: start + 19 * 60 * 60,
+        },
+        Span {
+            enter: start + 33 * 60 * 60,
+            leave: start + 38 * 60 * 60,
+        },
+    ]);
+
+    let month = OutputMonth::new(
+        &context,
+        "Eddie".into(),
+        start,
+        false,
+        spans.clone(),
+        Some(600),
+    );
+    assert_eq!(month.minutes, 900);
+    assert_eq!(month.overtime_minutes, Some(300));
+    let json = serde_json::to_value(&month).unwrap();
+    assert_eq!(json["overtime_minutes"], 300);
+
+    let month = OutputMonth::new(
+        &context,
+        "Eddie".into(),
+        start,
+        false,
+        spans.clone(),
+        Some(1200),
+    );
+    assert_eq!(month.overtime_minutes, Some(0));
+
+    let month = OutputMonth::new(&context, "Eddie".into(), start, false, spans, None);
+    assert_eq!(month.overtime_minutes, None);
+    let json = serde_json::to_value(&month).unwrap();
+    assert!(json["overtime_minutes"].is_null());
+}
+
+#[test]
+fn test_localized() {
+    use chrono_tz::Tz;
+    let span = Span {
+        enter: 1_700_000_000,
+        leave: 1_700_003_600,
+    };
+    let outputs = [
+        Output::PleasePromoteTheBot,
+        Output::Ok,
+        Output::Failure,
+        Output::YourAreNotPartOfAGroup,
+        Output::CouldNotRecognizeCommand,
+        Output::Help,
+        Output::Status {
+            entered_since: Some(span.enter),
+            today_minutes: 90,
+        },
+        Output::Status {
+            entered_since: None,
+            today_minutes: 0,
+        },
+        Output::DaySummary {
+            spans: Vec::from([span]),
+            open: Some(span),
+        },
+        Output::DaySummary {
+            spans: Vec::new(),
+            open: None,
+        },
+        Output::TimeZones(Vec::from([("Paris", Tz::Europe__Paris)])),
+        Output::Persons(Vec::from([(1, "Eddie".to_string())])),
+        Output::PersonRemoved {
+            person: 1,
+            name: "Eddie".into(),
+        },
+        Output::AdminSet {
+            person: 1,
+            name: "Eddie".into(),
+            admin: true,
+        },
+        Output::AdminSet {
+            person: 1,
+            name: "Eddie".into(),
+            admin: false,
+        },
+        Output::PermissionDenied,
+        Output::UnknownPerson,
+        Output::UnknownGroup,
+        Output::UnknownTimeZone("Madird".into()),
+        Output::EditIgnored,
+        Output::SlowDown,
+        Output::SpanAdded(span),
+        Output::Entered(span.enter),
+        Output::SpanHasEarlierLeaveThanEnter(span),
+        Output::SpanOverrodeSpans(Vec::from([span])),
+        Output::ClearedSpans {
+            day: span.enter,
+            spans: Vec::new(),
+        },
+        Output::ClearedSpans {
+            day: span.enter,
+            spans: Vec::from([span]),
+        },
+        Output::ConfirmClear {
+            day: span.enter,
+            spans: Vec::from([span]),
+        },
+        Output::NothingToConfirm,
+        Output::EnterOverrodeEntered(span.enter),
+        Output::AutoClosedEntry { person: 1, span },
+        Output::Rounded(Vec::from([(span.enter + 7 * 60, span.enter + 5 * 60)])),
+        Output::TryLeaveButNotEntered,
+        Output::CouldNotInferMinute,
+        Output::CouldNotInferDay,
+        Output::CouldNotInferWeek,
+        Output::CouldNotInferMonth,
+        Output::TooManyHours,
+        Output::Month {
+            format: DocFormat::Png,
+            person: 1,
+            name: "Eddie".into(),
+            month: span.enter,
+            week: false,
+            spans: Vec::from([span]),
+            absences: Vec::new(),
+            contracted_minutes: None,
+            time_zone: Tz::UTC,
+        },
+        Output::AbsenceMarked {
+            day: span.enter,
+            kind: AbsenceKind::Sick,
+        },
+        Output::CouldNotGenerateDocument,
+        Output::IAmNowAdministrator,
+    ];
+    for output in outputs {
+        let mut texts = Vec::new();
+        for language in [Language::En, Language::Es] {
+            let context = Context {
+                chat: 0,
+                thread: None,
+                date: span.enter,
+                language,
+                time_zone: Tz::Europe__Madrid,
+            };
+            let text = output.text(&context);
+            if let Text::Markdown(ref markdown) = text {
+                // reserved by MarkdownV2, and not used for styling here
+                let mut chars = markdown.chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '.' | '!' | '(' | ')' | '[' | ']' | '-' | '+' | '=' | '#' | '>' | '|'
+                        | '{' | '}' | '~' | '`' => {
+                            panic!("unescaped {c:?} in {markdown:?}")
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            let (Text::Plain(text) | Text::Markdown(text)) = text;
+            assert!(!text.trim().is_empty(), "{output:?} in {language:?}");
+            texts.push(text);
+        }
+        // ok and fail are the only untranslated ones
+        if !matches!(output, Output::Ok | Output::Failure) {
+            assert_ne!(texts[0], texts[1], "{output:?}");
+        }
+    }
+}
+
+#[test]
+fn test_render_failure() {
+    use chrono_tz::Tz;
+    let context = Context {
+        chat: 0,
+        thread: None,
+        date: 1_700_000_000,
+        language: Language::Es,
+        time_zone: Tz::Europe__Madrid,
+    };
+    let month = OutputMonth::new(
+        &context,
+        "Eddie".into(),
+        context.date,
+        false,
+        Vec::new(),
+        None,
+    );
+    let renderer = Renderer::new();
+    let result = month.render(&renderer, "#let broken = (", DocFormat::Png);
+    let Err(output) = result else {
+        panic!("broken template should not render");
+    };
+    assert!(matches!(output, Output::CouldNotGenerateDocument));
+    let Text::Plain(text) = output.text(&context) else {
+        panic!("failure message should be plain text");
+    };
+    assert!(text.contains("informe"));
+}
+
+#[test]
+fn test_render_pages() {
+    use chrono_tz::Tz;
+    let context = Context {
+        chat: 0,
+        thread: None,
+        date: 1_700_000_000,
+        language: Language::En,
+        time_zone: Tz::Europe__Madrid,
+    };
+    let spans = (0..42)
+        .map(|day| Span {
+            enter: context.date + day * 86400,
+            leave: context.date + day * 86400 + 3600,
+        })
+        .collect();
+    let month = OutputMonth::new(&context, "Eddie".into(), context.date, false, spans, None);
+    let renderer = Renderer::new();
+    let pages = month
+        .render(&renderer, include_str!("month.typ"), DocFormat::Png)
+        .unwrap();
+    assert_eq!(pages.len(), 2);
+    let pdf = month
+        .render(&renderer, include_str!("month.typ"), DocFormat::Pdf)
+        .unwrap();
+    assert_eq!(pdf.len(), 1);
+}
+
+#[test]
+fn test_nearest_time_zone() {
+    assert_eq!(edit_distance("madird", "madrid"), 1);
+    assert_eq!(edit_distance("madird", "madeira"), 2);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(nearest_time_zone("Madird"), Some("Europe/Madrid"));
+    assert_eq!(nearest_time_zone("europe/pariss"), Some("Europe/Paris"));
+    assert_eq!(nearest_time_zone("Xyzzyqwerty"), None);
+
+    let context = Context {
+        chat: 0,
+        thread: None,
+        date: 0,
+        language: Language::En,
+        time_zone: Tz::UTC,
+    };
+    let Text::Plain(text) = Output::UnknownTimeZone("Madird".into()).text(&context) else {
+        panic!("expected a plain text");
+    };
+    assert!(text.contains("Madird"), "{text}");
+    assert!(text.ends_with("Did you mean Europe/Madrid?"), "{text}");
+}
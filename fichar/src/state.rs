@@ -1,24 +1,37 @@
 use crate::{
-    command::{self, Command},
+    command::{self, Command, ParseError, TIME_ZONES, Target},
     context::Context,
     gen_key,
     input::Input,
     key_to_hex,
     language::Language,
     output::Output,
-    state::instance::{AddSpanError, Instance, LeaveError, Span},
+    state::instance::{AbsenceKind, AddSpanError, Inconsistency, Instance, LeaveError, Span},
 };
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce, aead::Aead};
 use axum::http::StatusCode;
 use chrono_tz::Tz;
+use rand::{TryRngCore, rngs::OsRng};
+use render::DocFormat;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::Write,
+    ops::Range,
+    path::Path,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::mpsc::{Receiver, Sender};
+use telegram::Bot;
+use time_util::TimeHintDay;
+use tokio::{
+    sync::mpsc::{Receiver, Sender},
+    time::Instant,
+};
 use tracing::{info, warn};
 
 pub mod instance;
+mod legacy;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Hook {
@@ -34,7 +47,203 @@ pub struct Hook {
 pub struct AppState {
     pub hook: Hook,
     instances: HashMap<i64, Instance>,
+    /// Instance the private messages of a person apply to, when chosen
+    active_instances: HashMap<i64, i64>,
+    /// Most recent update ids, to drop the ones Telegram delivers again
+    processed_updates: VecDeque<u64>,
+    /// Inputs processed since the last save, when they are appended to the event log
+    #[serde(skip)]
+    events: Vec<Event>,
+    /// Longest time changes stay only in memory while inputs keep arriving
+    pub save_interval: Duration,
+    /// Quiet time after an input before the changes are saved
+    pub save_debounce: Duration,
+    pub rate_limit: RateLimit,
+    /// Messages left to each person in each chat, not worth persisting
+    #[serde(skip)]
+    buckets: HashMap<(i64, i64), Bucket>,
+    /// Encrypts the state at rest when set
+    #[serde(skip)]
+    key: Option<[u8; 32]>,
+}
+/// Token bucket applied to the messages of each person in each chat, so that a busy group
+/// does not hold back the ones who are quiet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Messages a person can send in a row
+    pub burst: u32,
+    /// Seconds to regain one message
+    pub interval: u32,
+}
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            burst: 20,
+            interval: 3,
+        }
+    }
+}
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: u32,
+    refilled: i64,
+    /// Whether the person was told to slow down since they ran out
+    warned: bool,
+}
+impl Bucket {
+    /// Consumes a token if one is left
+    fn take(&mut self, rate_limit: RateLimit, now: i64) -> bool {
+        let interval = i64::from(rate_limit.interval.max(1));
+        let gained = (now - self.refilled).max(0) / interval;
+        self.tokens = (i64::from(self.tokens) + gained).min(i64::from(rate_limit.burst)) as u32;
+        self.refilled += gained * interval;
+        if self.tokens == rate_limit.burst {
+            self.refilled = now;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        self.warned = false;
+        true
+    }
+}
+/// Encrypts the bytes, prefixed with the random nonce used
+fn seal(key: &[u8; 32], bytes: &[u8]) -> Vec<u8> {
+    let mut nonce = [0; 12];
+    OsRng.try_fill_bytes(&mut nonce).unwrap();
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let mut buffer = Vec::from(nonce);
+    buffer.extend(cipher.encrypt(Nonce::from_slice(&nonce), bytes).unwrap());
+    buffer
+}
+fn open(key: &[u8; 32], bytes: &[u8]) -> Option<Vec<u8>> {
+    let (nonce, bytes) = bytes.split_first_chunk::<12>()?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher.decrypt(Nonce::from_slice(nonce), bytes).ok()
+}
+
+impl From<legacy::AppState> for AppState {
+    fn from(legacy: legacy::AppState) -> Self {
+        Self {
+            instances: legacy
+                .instances
+                .into_iter()
+                .map(|(key, instance)| (key, instance.into()))
+                .collect(),
+            ..Self::with_hook(legacy.hook.into())
+        }
+    }
+}
+
+impl From<legacy::Hook> for Hook {
+    fn from(legacy: legacy::Hook) -> Self {
+        Self {
+            port: legacy.port,
+            domain: legacy.domain,
+            bot_token: legacy.bot_token,
+            secret_token: legacy.secret_token,
+            cert_cert: legacy.cert_cert,
+            cert_key: legacy.cert_key,
+        }
+    }
+}
+/// Input as it was processed, with what its command reported to have changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub now: i64,
+    pub input: Input,
+    pub changes: Vec<Change>,
 }
+
+/// Change made to a person, the sender of the input or the one it targeted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change {
+    Entered {
+        person: i64,
+        enter: i64,
+    },
+    SpanAdded {
+        person: i64,
+        span: Span,
+    },
+    SpanRemoved {
+        person: i64,
+        span: Span,
+    },
+    Absence {
+        person: i64,
+        day: i64,
+        kind: AbsenceKind,
+    },
+    PersonRemoved {
+        person: i64,
+    },
+    Admin {
+        person: i64,
+        admin: bool,
+    },
+}
+
+impl Change {
+    /// Changes reported by the outputs of an input of `sender`, overridden spans belong to
+    /// the person of the span added just before them
+    fn from_outputs<'a>(sender: i64, outputs: impl IntoIterator<Item = &'a Output>) -> Vec<Self> {
+        let mut changes = Vec::new();
+        let mut person = sender;
+        for output in outputs {
+            match *output {
+                Output::Entered(enter) => changes.push(Self::Entered {
+                    person: sender,
+                    enter,
+                }),
+                Output::SpanAdded(span) => {
+                    person = sender;
+                    changes.push(Self::SpanAdded { person, span });
+                }
+                Output::AutoClosedEntry {
+                    person: closed,
+                    span,
+                } => {
+                    person = closed;
+                    changes.push(Self::SpanAdded { person, span });
+                }
+                Output::SpanOverrodeSpans(ref spans) => {
+                    changes.extend(spans.iter().map(|&span| Self::SpanRemoved { person, span }))
+                }
+                Output::ClearedSpans { ref spans, .. } => {
+                    changes.extend(spans.iter().map(|&span| Self::SpanRemoved {
+                        person: sender,
+                        span,
+                    }))
+                }
+                Output::AbsenceMarked { day, kind } => changes.push(Self::Absence {
+                    person: sender,
+                    day,
+                    kind,
+                }),
+                Output::PersonRemoved { person, .. } => {
+                    changes.push(Self::PersonRemoved { person })
+                }
+                Output::AdminSet { person, admin, .. } => {
+                    changes.push(Self::Admin { person, admin })
+                }
+                _ => {}
+            }
+        }
+        changes
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
 impl Hook {
     pub fn reset(self) -> Self {
         let certificate = rcgen::generate_simple_self_signed([self.domain.clone()]).unwrap();
@@ -63,20 +272,46 @@ impl Hook {
     pub fn port(self, port: u16) -> Self {
         Self { port, ..self }
     }
+    /// Stands for a secret left out of an export
+    const REDACTED: &str = "<redacted>";
+    fn redacted(self) -> Self {
+        Self {
+            bot_token: Self::REDACTED.into(),
+            secret_token: Self::REDACTED.into(),
+            cert_key: Self::REDACTED.into(),
+            ..self
+        }
+    }
+    pub fn is_redacted(&self) -> bool {
+        [&self.bot_token, &self.secret_token, &self.cert_key]
+            .into_iter()
+            .any(|secret| secret == Self::REDACTED)
+    }
+    /// Takes the secrets left out of an export from the hook in use
+    pub fn restore_secrets(&mut self, current: Hook) {
+        for (secret, current) in [
+            (&mut self.bot_token, current.bot_token),
+            (&mut self.secret_token, current.secret_token),
+            (&mut self.cert_key, current.cert_key),
+        ] {
+            if secret == Self::REDACTED {
+                *secret = current;
+            }
+        }
+    }
     pub async fn set(&self) {
         let mut cooldown = 8;
-        while !telegram::set_webhook(
-            &self.bot_token,
-            format!("https://{}:{}", self.domain, self.port),
-        )
-        .drop_pending_updates()
-        .certificate(self.cert_cert.clone().into())
-        .secret_token(self.secret_token.clone())
-        .send()
-        .await
-        .map(|response| response.status())
-        .unwrap_or(StatusCode::BAD_REQUEST)
-        .is_success()
+        let bot = Bot::new(self.bot_token.clone());
+        while !bot
+            .set_webhook(format!("https://{}:{}", self.domain, self.port))
+            .drop_pending_updates()
+            .certificate(self.cert_cert.clone().into())
+            .secret_token(self.secret_token.clone())
+            .send()
+            .await
+            .map(|response| response.status())
+            .unwrap_or(StatusCode::BAD_REQUEST)
+            .is_success()
         {
             warn!("failed to set webhook, retrying in {cooldown} seconds...");
             tokio::time::sleep(Duration::from_secs(cooldown)).await;
@@ -90,82 +325,425 @@ impl AppState {
     const FILE_PATH: &str = "state.postcard";
     const FILE_PATH_TMP: &str = "state.postcard.tmp";
     const FILE_PATH_BAK: &str = "state.postcard.bak";
-    pub fn load() -> Self {
-        let bytes = std::fs::read(Self::FILE_PATH).unwrap();
-        postcard::from_bytes(&bytes).unwrap()
-    }
-    pub fn save(&self) {
-        let bytes = postcard::to_allocvec(self).unwrap();
-        std::fs::write(Self::FILE_PATH_TMP, &bytes).unwrap();
-        std::fs::rename(Self::FILE_PATH, Self::FILE_PATH_BAK).ok();
-        std::fs::rename(Self::FILE_PATH_TMP, Self::FILE_PATH).unwrap();
+    /// Every input processed, in order, to audit and rebuild the instances
+    const EVENTS_PATH: &str = "events.jsonl";
+    /// Starts every saved state, the unversioned layout cannot start with it as its first
+    /// byte would be a port Telegram does not allow for webhooks
+    const MAGIC: &[u8] = b"fichar-state";
+    /// Bumped whenever the layout of the state changes, old layouts are migrated on load
+    const VERSION: u8 = 1;
+    const PROCESSED_UPDATES_CAPACITY: usize = 1024;
+    pub const DEFAULT_SAVE_INTERVAL: Duration = Duration::from_secs(2 * 60);
+    pub const DEFAULT_SAVE_DEBOUNCE: Duration = Duration::from_secs(1);
+    /// Loads the state, falling back to the backup if the file is unreadable
+    pub fn load(key: Option<[u8; 32]>) -> Self {
+        Self::load_from(
+            Path::new(Self::FILE_PATH),
+            Path::new(Self::FILE_PATH_BAK),
+            key,
+        )
+        .expect("failed to load state and its backup")
+    }
+    fn load_from(path: &Path, backup: &Path, key: Option<[u8; 32]>) -> Option<Self> {
+        let read = |path: &Path| Self::from_bytes(&std::fs::read(path).ok()?, key);
+        read(path).or_else(|| {
+            warn!("failed to load state, falling back to backup");
+            read(backup)
+        })
+    }
+    /// Appends the new events to the log, then writes the state to a temporary file and
+    /// renames it over the previous one, which is kept as a backup
+    pub fn save(&mut self) {
+        self.append_events(Path::new(Self::EVENTS_PATH));
+        self.save_to(
+            Path::new(Self::FILE_PATH),
+            Path::new(Self::FILE_PATH_TMP),
+            Path::new(Self::FILE_PATH_BAK),
+        );
         info!("state writen to disk");
     }
+    /// Events of the log, in the order they were processed
+    pub fn load_events(key: Option<[u8; 32]>) -> Vec<Event> {
+        Self::read_events(Path::new(Self::EVENTS_PATH), key)
+    }
+    /// Writes the events since the last save, one JSON object per line, a line is sealed and
+    /// written in hex when the state is encrypted
+    fn append_events(&mut self, path: &Path) {
+        if self.events.is_empty() {
+            return;
+        }
+        let mut text = String::new();
+        for event in &self.events {
+            let line = serde_json::to_string(event).unwrap();
+            match self.key {
+                Some(key) => text.push_str(&to_hex(&seal(&key, line.as_bytes()))),
+                None => text.push_str(&line),
+            }
+            text.push('\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        file.sync_all().unwrap();
+        self.events.clear();
+    }
+    /// Stops at the first unreadable line, as left by a crash in the middle of a write
+    fn read_events(path: &Path, key: Option<[u8; 32]>) -> Vec<Event> {
+        let Ok(bytes) = std::fs::read(path) else {
+            return Vec::new();
+        };
+        let mut events = Vec::new();
+        for line in bytes
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+        {
+            // lines written before a passphrase was set stay in plain
+            let event = serde_json::from_slice(line).ok().or_else(|| {
+                let line = open(&key?, &from_hex(line)?)?;
+                serde_json::from_slice(&line).ok()
+            });
+            let Some(event) = event else {
+                warn!("event log has an unreadable line");
+                break;
+            };
+            events.push(event);
+        }
+        events
+    }
+    /// Human readable form of the state, to inspect or fix it by hand, the secrets of the
+    /// hook are left out unless asked for
+    pub fn export(&self, secrets: bool) -> String {
+        let hook = match secrets {
+            true => self.hook.clone(),
+            false => self.hook.clone().redacted(),
+        };
+        serde_json::to_string_pretty(&Self {
+            hook,
+            ..self.clone()
+        })
+        .unwrap()
+    }
+    pub fn import(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+    /// Sets the key the state is encrypted with from now on
+    pub fn with_key(self, key: Option<[u8; 32]>) -> Self {
+        Self { key, ..self }
+    }
+    /// Serialized state after its version, the whole prefixed with a random nonce when
+    /// encrypted
+    fn to_bytes(&self) -> Vec<u8> {
+        let bytes = self.encode();
+        match self.key {
+            Some(key) => seal(&key, &bytes),
+            None => bytes,
+        }
+    }
+    fn from_bytes(bytes: &[u8], key: Option<[u8; 32]>) -> Option<Self> {
+        let state = match key.map(|key| open(&key, bytes)) {
+            None => Self::decode(bytes)?,
+            Some(Some(bytes)) => Self::decode(&bytes)?,
+            // saved before a passphrase was set, it is encrypted by the next save
+            Some(None) => {
+                let state = Self::decode(bytes)?;
+                warn!("state is not encrypted, it will be on the next save");
+                state
+            }
+        };
+        Some(state.with_key(key))
+    }
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::from(Self::MAGIC);
+        bytes.push(Self::VERSION);
+        bytes.extend(postcard::to_allocvec(self).unwrap());
+        bytes
+    }
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        match bytes.strip_prefix(Self::MAGIC) {
+            Some([Self::VERSION, bytes @ ..]) => postcard::from_bytes(bytes).ok(),
+            Some(_) => {
+                warn!("state saved by a newer version");
+                None
+            }
+            // the whole input must be used, for garbage to be unlikely to pass for a state
+            None => match postcard::take_from_bytes::<legacy::AppState>(bytes) {
+                Ok((legacy, [])) => {
+                    info!("migrating state from the unversioned layout");
+                    Some(legacy.into())
+                }
+                _ => None,
+            },
+        }
+    }
+    fn save_to(&self, path: &Path, tmp: &Path, backup: &Path) {
+        let bytes = self.to_bytes();
+        let mut file = File::create(tmp).unwrap();
+        file.write_all(&bytes).unwrap();
+        file.sync_all().unwrap();
+        std::fs::rename(path, backup).ok();
+        std::fs::rename(tmp, path).unwrap();
+    }
     pub async fn process_inputs(
+        self,
+        receiver: Receiver<(u64, Input)>,
+        output: Sender<(Output, Context)>,
+    ) -> Self {
+        self.process_inputs_with(receiver, output, Self::save).await
+    }
+    /// Processes inputs until the input channel closes
+    ///
+    /// If the output channel closes first, the state is saved and the inputs
+    /// already received are still processed, their outputs being dropped.
+    async fn process_inputs_with(
         mut self,
-        mut receiver: Receiver<Input>,
-        mut output: Sender<(Output, Context)>,
+        mut receiver: Receiver<(u64, Input)>,
+        output: Sender<(Output, Context)>,
+        save: impl Fn(&mut Self),
     ) -> Self {
+        let now = || {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64
+        };
+        let mut auto_save =
+            tokio::time::interval_at(Instant::now() + self.save_interval, self.save_interval);
+        let debounce = tokio::time::sleep(self.save_debounce);
+        tokio::pin!(debounce);
+        let mut dirty = false;
         loop {
             tokio::select! {
                 // auto-save, must be first to avoid starvation when lots of inputs arrive
-                _ = tokio::time::sleep(Duration::from_secs(60 * 60)) => {
-                    self.save();
+                biased;
+                _ = auto_save.tick(), if dirty => {
+                    save(&mut self);
+                    dirty = false;
+                }
+                _ = &mut debounce, if dirty => {
+                    save(&mut self);
+                    dirty = false;
                 }
                 input = receiver.recv() => {
-                    let Some(input) = input else {
+                    let Some((update_id, input)) = input else {
                         return self;
                     };
-                    self.input(input, &mut output).await;
+                    dirty = true;
+                    debounce.as_mut().reset(Instant::now() + self.save_debounce);
+                    for this_output in self.update(update_id, input, now()) {
+                        if output.send(this_output).await.is_err() {
+                            warn!("output channel closed, draining remaining inputs");
+                            save(&mut self);
+                            receiver.close();
+                            while let Some((update_id, input)) = receiver.recv().await {
+                                self.update(update_id, input, now());
+                            }
+                            return self;
+                        }
+                    }
                 }
             }
         }
     }
     pub fn new(bot_token: String, domain: String, port: u16) -> Self {
+        Self::with_hook(Hook::init(bot_token, domain).port(port))
+    }
+    fn with_hook(hook: Hook) -> Self {
         Self {
-            hook: Hook::init(bot_token, domain).port(port),
+            hook,
             instances: HashMap::new(),
+            active_instances: HashMap::new(),
+            processed_updates: VecDeque::new(),
+            events: Vec::new(),
+            save_interval: Self::DEFAULT_SAVE_INTERVAL,
+            save_debounce: Self::DEFAULT_SAVE_DEBOUNCE,
+            rate_limit: RateLimit::default(),
+            buckets: HashMap::new(),
+            key: None,
+        }
+    }
+    /// Instance the private messages of the person apply to: the one they switched to,
+    /// or else their personal one, or else the group with the lowest id they are part of
+    fn active_instance(&self, person: i64) -> Option<i64> {
+        let part_of = |key: &i64| {
+            self.instances
+                .get(key)
+                .is_some_and(|instance| instance.person(person).is_some())
+        };
+        self.active_instances
+            .get(&person)
+            .copied()
+            .filter(part_of)
+            .or(Some(person).filter(part_of))
+            .or_else(|| self.instances.keys().copied().filter(part_of).min())
+    }
+    fn switch(&mut self, person: i64, group: &str) -> Vec<Output> {
+        let key = self.instances.iter().find_map(|(key, instance)| {
+            let name = instance.name.as_deref()?;
+            (instance.person(person).is_some() && name.to_lowercase() == group.to_lowercase())
+                .then_some(*key)
+        });
+        match key {
+            Some(key) => {
+                self.active_instances.insert(person, key);
+                Vec::from([Output::Ok])
+            }
+            None => Vec::from([Output::Failure, Output::UnknownGroup]),
+        }
+    }
+    /// Processes the input of an update, unless it was already processed
+    pub fn update(&mut self, update_id: u64, input: Input, now: i64) -> Vec<(Output, Context)> {
+        if self.processed_updates.contains(&update_id) {
+            info!("dropping update {update_id} delivered again");
+            return Vec::new();
+        }
+        if self.processed_updates.len() == Self::PROCESSED_UPDATES_CAPACITY {
+            self.processed_updates.pop_front();
+        }
+        self.processed_updates.push_back(update_id);
+        if let Input::Text {
+            chat,
+            thread,
+            person,
+            ref language_code,
+            date,
+            ..
+        } = input
+        {
+            let bucket = self.buckets.entry((chat, person)).or_insert(Bucket {
+                tokens: self.rate_limit.burst,
+                refilled: now,
+                warned: false,
+            });
+            if !bucket.take(self.rate_limit, now) {
+                info!(
+                    "dropping update {update_id}, person {person} is rate limited in chat {chat}"
+                );
+                if bucket.warned {
+                    return Vec::new();
+                }
+                bucket.warned = true;
+                let language = match self.instances.get(&chat) {
+                    Some(instance) => instance.language,
+                    None => language_code
+                        .as_deref()
+                        .map(Language::from_code)
+                        .unwrap_or(Language::En),
+                };
+                let context = Context {
+                    chat,
+                    thread,
+                    date,
+                    language,
+                    time_zone: Tz::UTC,
+                };
+                return Vec::from([(Output::SlowDown, context)]);
+            }
         }
+        self.handle(input, now)
     }
-    pub async fn input(&mut self, input: Input, output: &mut Sender<(Output, Context)>) {
+    pub fn instances(&self) -> usize {
+        self.instances.len()
+    }
+    /// Inconsistencies of every instance, by instance
+    pub fn validate(&self) -> Vec<(i64, Inconsistency)> {
+        let mut keys: Vec<i64> = self.instances.keys().copied().collect();
+        keys.sort_unstable();
+        keys.into_iter()
+            .flat_map(|key| {
+                self.instances[&key]
+                    .validate()
+                    .into_iter()
+                    .map(move |inconsistency| (key, inconsistency))
+            })
+            .collect()
+    }
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+    /// Processes the inputs of the events again, in order, without logging them a second time
+    pub fn replay(&mut self, events: &[Event]) {
+        for event in events {
+            self.apply(event.input.clone(), event.now);
+        }
+    }
+    /// Processes an input, giving the messages to send, `now` is the current instant
+    pub fn handle(&mut self, input: Input, now: i64) -> Vec<(Output, Context)> {
+        let outputs = self.apply(input.clone(), now);
+        let changes =
+            Change::from_outputs(input.person(), outputs.iter().map(|(output, _)| output));
+        self.events.push(Event {
+            now,
+            input,
+            changes,
+        });
+        outputs
+    }
+    fn apply(&mut self, input: Input, now: i64) -> Vec<(Output, Context)> {
+        let mut outputs = Vec::new();
         match input {
             Input::Text {
                 user,
                 chat,
+                thread,
                 group,
+                title,
                 person,
+                language_code,
                 date,
                 text,
+                edited,
             } => {
-                let instance = if group {
-                    Some(
-                        self.instances
-                            .entry(chat)
-                            .or_insert_with(Instance::new_spain)
-                            .with_person(person),
-                    )
+                let language = language_code.as_deref().map(Language::from_code);
+                let key = if group {
+                    let instance = self.instances.entry(chat).or_insert_with(|| {
+                        let mut instance = match language {
+                            Some(language) => Instance::new(language, Tz::Europe__Madrid),
+                            None => Instance::new_spain(),
+                        };
+                        // the first person to talk in a new group manages it
+                        instance.set_admin(person, true);
+                        instance
+                    });
+                    if title.is_some() {
+                        instance.name = title;
+                    }
+                    instance.with_person(person);
+                    Some(chat)
                 } else {
-                    self.instances
-                        .values_mut()
-                        .find(|instance| instance.person(person).is_some())
+                    match self.active_instance(person) {
+                        Some(key) => Some(key),
+                        // a private chat of a person part of no group, they track their hours
+                        // on their own
+                        None if chat == person => {
+                            let mut instance =
+                                Instance::new(language.unwrap_or(Language::En), Tz::Europe__Madrid);
+                            instance.set_admin(person, true);
+                            self.instances.insert(person, instance);
+                            Some(person)
+                        }
+                        None => None,
+                    }
                 };
+                let instance = key.and_then(|key| self.instances.get_mut(&key));
 
                 match instance {
                     None => {
                         let context = Context {
                             chat,
+                            thread,
                             date,
-                            language: Language::En,
+                            language: language.unwrap_or(Language::En),
                             time_zone: Tz::UTC,
                         };
-                        output
-                            .send((Output::YourAreNotPartOfAGroup, context))
-                            .await
-                            .unwrap();
+                        outputs.push((Output::YourAreNotPartOfAGroup, context));
                     }
                     Some(instance) => {
                         let context = Context {
                             chat,
+                            thread,
                             date,
                             language: instance.language,
                             time_zone: instance.time_zone,
@@ -177,71 +755,78 @@ impl AppState {
                             instance.set_last_name(person, last_name);
                         }
                         match command::parse(context.language, &text) {
-                            Err(()) => {
-                                output
-                                    .send((Output::CouldNotRecognizeCommand, context))
-                                    .await
-                                    .unwrap();
+                            Err(ParseError::Unrecognized) => {
+                                outputs.push((Output::CouldNotRecognizeCommand, context));
+                            }
+                            Err(ParseError::UnknownTimeZone(time_zone)) => {
+                                outputs.push((Output::UnknownTimeZone(time_zone), context));
+                            }
+                            Ok(command) if edited && !command.runs_when_edited() => {
+                                outputs.push((Output::EditIgnored, context));
+                            }
+                            Ok(Command::Switch { group }) => {
+                                for this_output in self.switch(person, &group) {
+                                    outputs.push((this_output, context));
+                                }
                             }
                             Ok(command) => {
-                                let mut outputs = Vec::new();
-                                instance.command(person, date, command, &mut outputs).await;
-                                for this_output in outputs {
-                                    output.send((this_output, context)).await.unwrap();
+                                let mut instance_outputs = Vec::new();
+                                instance.command(person, date, command, &mut instance_outputs);
+                                for this_output in instance_outputs {
+                                    outputs.push((this_output, context));
                                 }
                             }
                         }
                     }
                 }
             }
-            Input::NewGroup { chat, name: _ } => {
-                self.instances.insert(chat, Instance::new_spain());
+            Input::NewGroup { chat, person, name } => {
+                let mut instance = Instance::new_spain();
+                instance.name = Some(name);
+                instance.set_admin(person, true);
+                self.instances.insert(chat, instance);
                 let context = Context {
                     chat,
-                    date: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as i64,
+                    thread: None,
+                    date: now,
                     language: Language::En,
                     time_zone: Tz::UTC,
                 };
-                output
-                    .send((Output::PleasePromoteTheBot, context))
-                    .await
-                    .unwrap();
+                outputs.push((Output::PleasePromoteTheBot, context));
             }
             Input::LeftChat { chat, person } => {
                 if let Some(instance) = self.instances.get_mut(&chat) {
                     instance.remove_person(person);
                 }
             }
-            Input::NowAdmin { chat } => {
+            Input::NowAdmin { chat, person } => {
+                // whoever promoted the bot manages the group
+                self.instances
+                    .entry(chat)
+                    .or_insert_with(Instance::new_spain)
+                    .set_admin(person, true);
                 let context = Context {
                     chat,
-                    date: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as i64,
+                    thread: None,
+                    date: now,
                     language: Language::En,
                     time_zone: Tz::UTC,
                 };
-                output
-                    .send((Output::IAmNowAdministrator, context))
-                    .await
-                    .unwrap();
+                outputs.push((Output::IAmNowAdministrator, context));
             }
         }
+        outputs
     }
 }
 
 impl Instance {
-    pub async fn command(
-        &mut self,
-        person: i64,
-        date: i64,
-        command: Command,
-        output: &mut Vec<Output>,
-    ) {
+    pub fn command(&mut self, person: i64, date: i64, command: Command, output: &mut Vec<Output>) {
+        if let Some((span, overriden)) = self.auto_close(person, date) {
+            output.push(Output::AutoClosedEntry { person, span });
+            if !overriden.is_empty() {
+                output.push(Output::SpanOverrodeSpans(overriden));
+            }
+        }
         let command = match command {
             Command::ClearHint { day } => match day.infer_past(self.time_zone, date) {
                 Some(day) => Command::Clear { day },
@@ -250,6 +835,13 @@ impl Instance {
                     return;
                 }
             },
+            Command::AbsenceHint { day, kind } => match day.infer_past(self.time_zone, date) {
+                Some(day) => Command::Absence { day, kind },
+                None => {
+                    output.push(Output::CouldNotInferDay);
+                    return;
+                }
+            },
             Command::SpanHint {
                 enter_day: Some(enter_day),
                 enter_minute,
@@ -322,31 +914,102 @@ impl Instance {
                     leave: leave.start,
                 }
             }
-            Command::EnterHint { time_hint } => match time_hint.infer(self.time_zone, date) {
-                Some(enter) => Command::Enter { enter: enter.start },
-                None => {
-                    output.push(Output::CouldNotInferMinute);
-                    return;
+            Command::EnterHint { day, time_hint } => {
+                let day = match day {
+                    Some(day) => match day.infer_past(self.time_zone, date) {
+                        Some(day) => day.start,
+                        None => {
+                            output.push(Output::CouldNotInferDay);
+                            return;
+                        }
+                    },
+                    None => date,
+                };
+                match time_hint.infer(self.time_zone, day) {
+                    Some(enter) => Command::Enter { enter: enter.start },
+                    None => {
+                        output.push(Output::CouldNotInferMinute);
+                        return;
+                    }
                 }
-            },
-            Command::LeaveHint { time_hint } => match time_hint.infer(self.time_zone, date) {
-                Some(leave) => Command::Leave { leave: leave.start },
-                None => {
-                    output.push(Output::CouldNotInferMinute);
-                    return;
+            }
+            Command::LeaveHint { day, time_hint } => {
+                let day = match day {
+                    Some(day) => match day.infer_past(self.time_zone, date) {
+                        Some(day) => day.start,
+                        None => {
+                            output.push(Output::CouldNotInferDay);
+                            return;
+                        }
+                    },
+                    None => date,
+                };
+                match time_hint.infer(self.time_zone, day) {
+                    Some(leave) => Command::Leave { leave: leave.start },
+                    None => {
+                        output.push(Output::CouldNotInferMinute);
+                        return;
+                    }
                 }
-            },
+            }
             Command::MonthHint {
                 time_hint,
                 format,
                 all,
-            } => match time_hint.infer(self.time_zone, date) {
-                Some(month) => Command::Month { month, format, all },
+                time_zone,
+            } => match time_hint.infer(time_zone.unwrap_or(self.time_zone), date) {
+                Some(month) => Command::Month {
+                    month,
+                    format: format.unwrap_or_else(|| self.format(person)),
+                    all,
+                    time_zone,
+                },
                 None => {
                     output.push(Output::CouldNotInferMonth);
                     return;
                 }
             },
+            Command::WeekHint {
+                time_hint,
+                format,
+                all,
+                time_zone,
+            } => {
+                match time_hint.infer(time_zone.unwrap_or(self.time_zone), date, self.week_start) {
+                    Some(week) => Command::Week {
+                        week,
+                        format: format.unwrap_or_else(|| self.format(person)),
+                        all,
+                        time_zone,
+                    },
+                    None => {
+                        output.push(Output::CouldNotInferWeek);
+                        return;
+                    }
+                }
+            }
+            other => other,
+        };
+        let mut rounded = Vec::new();
+        let (rounding, time_zone) = (self.rounding, self.time_zone);
+        let mut round = |instant: i64| {
+            let stored = rounding.apply(instant, time_zone);
+            if stored != instant {
+                rounded.push((instant, stored));
+            }
+            stored
+        };
+        let command = match command {
+            Command::Span { enter, leave } => Command::Span {
+                enter: round(enter),
+                leave: round(leave),
+            },
+            Command::Enter { enter } => Command::Enter {
+                enter: round(enter),
+            },
+            Command::Leave { leave } => Command::Leave {
+                leave: round(leave),
+            },
             other => other,
         };
         match command {
@@ -355,22 +1018,150 @@ impl Instance {
                 output.push(Output::Help);
             }
             Command::Nope => {}
-            Command::Clear { day } => {
-                let removed = self.clear(person, day.start, day.end);
+            Command::Status => {
+                let today_minutes = match TimeHintDay::None.infer_past(self.time_zone, date) {
+                    Some(today) => self
+                        .select(person, today.start, today.end)
+                        .into_iter()
+                        .map(Span::minutes)
+                        .sum(),
+                    None => 0,
+                };
                 output.push(Output::Ok);
-                output.push(Output::ClearedSpans {
-                    spans: removed,
-                    day: day.start,
+                output.push(Output::Status {
+                    entered_since: self.entered(person),
+                    today_minutes,
                 });
             }
+            Command::Today => match TimeHintDay::None.infer_past(self.time_zone, date) {
+                Some(today) => {
+                    let spans = self.select(person, today.start, today.end);
+                    let open = self
+                        .entered(person)
+                        .and_then(|enter| Span { enter, leave: date }.conjunction(today));
+                    output.push(Output::Ok);
+                    output.push(Output::DaySummary { spans, open });
+                }
+                None => {
+                    output.push(Output::Failure);
+                    output.push(Output::CouldNotInferDay);
+                }
+            },
+            Command::TimeZones => {
+                let time_zones = TIME_ZONES
+                    .iter()
+                    .map(|&(en, es, time_zone)| match self.language {
+                        Language::En => (en, time_zone),
+                        Language::Es => (es, time_zone),
+                    })
+                    .collect();
+                output.push(Output::Ok);
+                output.push(Output::TimeZones(time_zones));
+            }
+            Command::Persons => {
+                let persons = self
+                    .listed_persons()
+                    .into_iter()
+                    .map(|person| {
+                        let name = self
+                            .get_name(person)
+                            .unwrap_or_else(|| "Unknown".to_string());
+                        (person, name)
+                    })
+                    .collect();
+                output.push(Output::Ok);
+                output.push(Output::Persons(persons));
+            }
+            Command::RemovePerson { .. }
+            | Command::SetAdmin { .. }
+            | Command::SetTimeZone { .. }
+            | Command::SetRounding { .. }
+            | Command::SetWeekStart { .. }
+            | Command::SetMergeGap { .. }
+            | Command::SetLanguage { .. }
+                if !self.is_admin(person) =>
+            {
+                output.push(Output::Failure);
+                output.push(Output::PermissionDenied);
+            }
+            Command::RemovePerson { target } => match self.resolve(person, target) {
+                Some(targets) => {
+                    output.push(Output::Ok);
+                    for target in targets {
+                        let name = self
+                            .get_name(target)
+                            .unwrap_or_else(|| "Unknown".to_string());
+                        self.remove_person(target);
+                        output.push(Output::PersonRemoved {
+                            person: target,
+                            name,
+                        });
+                    }
+                }
+                None => {
+                    output.push(Output::Failure);
+                    output.push(Output::UnknownPerson);
+                }
+            },
+            Command::SetAdmin { target, admin } => match self.resolve(person, target) {
+                Some(targets) => {
+                    output.push(Output::Ok);
+                    for target in targets {
+                        self.set_admin(target, admin);
+                        let name = self
+                            .get_name(target)
+                            .unwrap_or_else(|| "Unknown".to_string());
+                        output.push(Output::AdminSet {
+                            person: target,
+                            name,
+                            admin,
+                        });
+                    }
+                }
+                None => {
+                    output.push(Output::Failure);
+                    output.push(Output::UnknownPerson);
+                }
+            },
+            Command::Clear { day } => {
+                let spans = self.clearable(person, day.start, day.end);
+                if spans.is_empty() {
+                    output.push(Output::Ok);
+                    output.push(Output::ClearedSpans {
+                        spans,
+                        day: day.start,
+                    });
+                } else {
+                    output.push(Output::Ok);
+                    output.push(Output::ConfirmClear {
+                        spans,
+                        day: day.start,
+                    });
+                    self.request_clear(person, day, date);
+                }
+            }
+            Command::ConfirmClear => match self.take_pending_clear(person, date) {
+                Some(day) => {
+                    let removed = self.clear(person, day.start, day.end);
+                    output.push(Output::Ok);
+                    output.push(Output::ClearedSpans {
+                        spans: removed,
+                        day: day.start,
+                    });
+                }
+                None => {
+                    output.push(Output::Failure);
+                    output.push(Output::NothingToConfirm);
+                }
+            },
             Command::Span { enter, leave } => match self.add_span(person, enter, leave) {
-                Ok(overriden) if overriden.is_empty() => {
+                Ok((added, overriden)) if overriden.is_empty() => {
                     output.push(Output::Ok);
-                    output.push(Output::SpanAdded(Span { enter, leave }));
+                    output.push(Output::SpanAdded(added));
                 }
-                Ok(overriden) => {
+                Ok((added, overriden)) => {
                     output.push(Output::Ok);
-                    output.push(Output::SpanAdded(Span { enter, leave }));
+                    output.push(Output::SpanAdded(added));
                     output.push(Output::SpanOverrodeSpans(overriden));
                 }
                 Err(AddSpanError::LeaveEarlierThanEnter(span)) => {
@@ -408,40 +1199,1481 @@ impl Instance {
                     output.push(Output::SpanHasEarlierLeaveThanEnter(span));
                 }
             },
-            Command::Month { month, format, all } => {
-                output.push(Output::Ok);
-                let persons = if all {
-                    self.persons().collect()
-                } else {
-                    Vec::from([person])
-                };
-
-                for person in persons {
-                    let name = self
-                        .get_name(person)
-                        .unwrap_or_else(|| "Unknown".to_string());
-                    output.push(Output::Month {
-                        person,
-                        name,
-                        format,
-                        month: month.start,
-                        spans: self.select(person, month.start, month.end),
-                    });
-                }
+            Command::Month {
+                month,
+                format,
+                all,
+                time_zone,
+            } => {
+                let time_zone = time_zone.unwrap_or(self.time_zone);
+                self.report(person, date, month, false, format, all, time_zone, output);
+            }
+            Command::Week {
+                week,
+                format,
+                all,
+                time_zone,
+            } => {
+                let time_zone = time_zone.unwrap_or(self.time_zone);
+                self.report(person, date, week, true, format, all, time_zone, output);
             }
             Command::SetTimeZone { time_zone } => {
                 self.time_zone = time_zone;
                 output.push(Output::Ok);
             }
+            Command::SetHours { hours } => match hours.checked_mul(60) {
+                Some(minutes) if minutes <= Self::MAX_MINUTES_PER_MONTH => {
+                    self.set_contracted_minutes(person, (minutes > 0).then_some(minutes));
+                    output.push(Output::Ok);
+                }
+                _ => {
+                    output.push(Output::Failure);
+                    output.push(Output::TooManyHours);
+                }
+            },
+            Command::Absence { day, kind } => {
+                self.set_absence(person, day.start, kind);
+                output.push(Output::Ok);
+                output.push(Output::AbsenceMarked {
+                    day: day.start,
+                    kind,
+                });
+            }
+            Command::SetWeekStart { week_start } => {
+                self.week_start = week_start;
+                output.push(Output::Ok);
+            }
+            Command::SetRounding { rounding } => {
+                self.rounding = rounding;
+                output.push(Output::Ok);
+            }
+            Command::SetMergeGap { minutes } => {
+                self.merge_gap_minutes = minutes;
+                output.push(Output::Ok);
+            }
+            Command::SetFormat { format } => {
+                self.set_format(person, format);
+                output.push(Output::Ok);
+            }
+            Command::SetName { name } => {
+                self.set_display_name(person, name);
+                output.push(Output::Ok);
+            }
             Command::SetLanguage { language } => {
                 self.language = language;
                 output.push(Output::Ok);
             }
             Command::ClearHint { .. } => unreachable!(),
+            Command::AbsenceHint { .. } => unreachable!(),
             Command::SpanHint { .. } => unreachable!(),
             Command::EnterHint { .. } => unreachable!(),
             Command::LeaveHint { .. } => unreachable!(),
             Command::MonthHint { .. } => unreachable!(),
+            Command::WeekHint { .. } => unreachable!(),
+            // switching crosses instances, it is handled by the app state
+            Command::Switch { .. } => unreachable!(),
+        }
+        if !rounded.is_empty() {
+            output.push(Output::Rounded(rounded));
+        }
+    }
+    /// Persons designated by the target, an index starts at one
+    fn resolve(&self, person: i64, target: Target) -> Option<Vec<i64>> {
+        match target {
+            Target::Me => Some(Vec::from([person])),
+            Target::All => Some(self.listed_persons()),
+            Target::Index(index) => {
+                let person = *self.listed_persons().get(index.checked_sub(1)?)?;
+                Some(Vec::from([person]))
+            }
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn report(
+        &mut self,
+        person: i64,
+        date: i64,
+        range: Range<i64>,
+        week: bool,
+        format: DocFormat,
+        all: bool,
+        time_zone: Tz,
+        output: &mut Vec<Output>,
+    ) {
+        output.push(Output::Ok);
+        let persons = if all {
+            self.persons().collect()
+        } else {
+            Vec::from([person])
+        };
+
+        for person in persons {
+            if let Some((span, overriden)) = self.auto_close(person, date) {
+                output.push(Output::AutoClosedEntry { person, span });
+                if !overriden.is_empty() {
+                    output.push(Output::SpanOverrodeSpans(overriden));
+                }
+            }
+            let name = self
+                .get_name(person)
+                .unwrap_or_else(|| "Unknown".to_string());
+            output.push(Output::Month {
+                person,
+                name,
+                format,
+                month: range.start,
+                week,
+                spans: self.select_in(person, range.start, range.end, time_zone),
+                absences: self.absences(person, range.start, range.end),
+                contracted_minutes: self.contracted_minutes(person),
+                time_zone,
+            });
         }
     }
 }
+
+/// 2023-11-14 22:13:20 UTC, a tuesday
+#[cfg(test)]
+const NOW: i64 = 1_700_000_000;
+
+/// Text of Eddie, in a group when `chat` is negative as Telegram numbers them
+#[cfg(test)]
+fn text_input(chat: i64, person: i64, date: i64, text: &str) -> Input {
+    Input::Text {
+        user: (Some("Eddie".into()), None),
+        chat,
+        thread: None,
+        group: chat < 0,
+        title: (chat < 0).then(|| "Team".into()),
+        person,
+        language_code: None,
+        date,
+        text: text.into(),
+        edited: false,
+    }
+}
+
+#[test]
+fn test_clear_confirmation() {
+    let date = NOW;
+    let day = 1_699_920_000..1_699_920_000 + 24 * 60 * 60;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    let span = Span {
+        enter: day.start + 9 * 60 * 60,
+        leave: day.start + 17 * 60 * 60,
+    };
+    instance.command(
+        1,
+        date,
+        Command::Span {
+            enter: span.enter,
+            leave: span.leave,
+        },
+        &mut output,
+    );
+
+    output.clear();
+    instance.command(1, date, Command::Clear { day: day.clone() }, &mut output);
+    assert!(matches!(&output[1], Output::ConfirmClear { spans, .. } if spans == &[span]));
+    assert_eq!(instance.select(1, day.start, day.end), [span]);
+
+    // someone else cannot confirm it
+    output.clear();
+    instance.command(2, date, Command::ConfirmClear, &mut output);
+    assert!(matches!(output[1], Output::NothingToConfirm));
+    assert_eq!(instance.select(1, day.start, day.end), [span]);
+
+    output.clear();
+    instance.command(1, date + 60, Command::ConfirmClear, &mut output);
+    assert!(matches!(&output[1], Output::ClearedSpans { spans, .. } if spans == &[span]));
+    assert!(instance.select(1, day.start, day.end).is_empty());
+
+    // confirming twice does nothing
+    output.clear();
+    instance.command(1, date + 60, Command::ConfirmClear, &mut output);
+    assert!(matches!(output[1], Output::NothingToConfirm));
+}
+
+#[test]
+fn test_clear_confirmation_timeout() {
+    let date = NOW;
+    let day = 1_699_920_000..1_699_920_000 + 24 * 60 * 60;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    instance.command(
+        1,
+        date,
+        Command::Span {
+            enter: day.start,
+            leave: day.start + 60 * 60,
+        },
+        &mut output,
+    );
+    instance.command(1, date, Command::Clear { day: day.clone() }, &mut output);
+    output.clear();
+    let late = date + Instance::CLEAR_CONFIRMATION_TIMEOUT + 1;
+    instance.command(1, late, Command::ConfirmClear, &mut output);
+    assert!(matches!(output[1], Output::NothingToConfirm));
+    assert_eq!(instance.select(1, day.start, day.end).len(), 1);
+}
+
+#[test]
+fn test_auto_close() {
+    let date = NOW;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    let enter = date - 20 * 60 * 60;
+    instance.command(1, enter, Command::Enter { enter }, &mut output);
+
+    output.clear();
+    instance.command(1, date, Command::Help, &mut output);
+    let span = Span {
+        enter,
+        leave: enter + instance.auto_close_duration,
+    };
+    assert!(matches!(
+        output[0],
+        Output::AutoClosedEntry { person: 1, span: closed } if closed == span
+    ));
+    assert_eq!(instance.entered(1), None);
+    assert_eq!(instance.select(1, enter, date), [span]);
+
+    // a recent entry is left alone
+    output.clear();
+    instance.command(1, date, Command::Enter { enter: date }, &mut output);
+    instance.command(1, date + 60 * 60, Command::Help, &mut output);
+    assert!(
+        !output
+            .iter()
+            .any(|o| matches!(o, Output::AutoClosedEntry { .. }))
+    );
+    assert_eq!(instance.entered(1), Some(date));
+}
+
+#[test]
+fn test_auto_close_overrides() {
+    let date = NOW;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    let enter = date - 20 * 60 * 60;
+    instance.command(1, enter, Command::Enter { enter }, &mut output);
+    // recorded while the entry was still open
+    let recorded = Span {
+        enter: enter + 60 * 60,
+        leave: enter + 2 * 60 * 60,
+    };
+    instance.command(
+        1,
+        recorded.leave,
+        Command::Span {
+            enter: recorded.enter,
+            leave: recorded.leave,
+        },
+        &mut output,
+    );
+
+    output.clear();
+    instance.command(1, date, Command::Help, &mut output);
+    assert!(matches!(output[0], Output::AutoClosedEntry { .. }));
+    assert!(matches!(&output[1], Output::SpanOverrodeSpans(overriden) if overriden == &[recorded]));
+}
+
+#[test]
+fn test_week() {
+    use time_util::TimeHintWeek;
+    // 2023-11-14 23:13:20 in Madrid, a tuesday
+    let date = NOW;
+    // 2023-11-13 00:00:00 in Madrid, the monday before
+    let monday = 1_699_830_000;
+    let next_monday = monday + 7 * 24 * 60 * 60;
+    let mut instance = Instance::new(Language::En, Tz::Europe__Madrid);
+    let mut output = Vec::new();
+    let inside = [
+        Span {
+            enter: monday,
+            leave: monday + 60 * 60,
+        },
+        Span {
+            enter: next_monday - 60 * 60,
+            leave: next_monday,
+        },
+    ];
+    let outside = [
+        Span {
+            enter: monday - 60 * 60,
+            leave: monday,
+        },
+        Span {
+            enter: next_monday,
+            leave: next_monday + 60 * 60,
+        },
+    ];
+    for span in inside.into_iter().chain(outside) {
+        instance.command(
+            1,
+            date,
+            Command::Span {
+                enter: span.enter,
+                leave: span.leave,
+            },
+            &mut output,
+        );
+    }
+    output.clear();
+    instance.command(
+        1,
+        date,
+        Command::WeekHint {
+            time_hint: TimeHintWeek::None,
+            format: Some(DocFormat::Png),
+            all: false,
+            time_zone: None,
+        },
+        &mut output,
+    );
+    let Output::Month {
+        month, week, spans, ..
+    } = &output[1]
+    else {
+        panic!("expected a document, found {:?}", output[1]);
+    };
+    assert_eq!(*month, monday);
+    assert!(*week);
+    assert_eq!(spans, &inside);
+}
+
+#[test]
+fn test_load_truncated_state() {
+    let dir = std::env::temp_dir().join(format!("fichar-test-state-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("state.postcard");
+    let tmp = dir.join("state.postcard.tmp");
+    let backup = dir.join("state.postcard.bak");
+
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    state.save_to(&path, &tmp, &backup);
+    state.hook.domain = "example.org".into();
+    state.save_to(&path, &tmp, &backup);
+    assert!(!tmp.exists());
+
+    // simulates a crash in the middle of a write
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+    let loaded = AppState::load_from(&path, &backup, None).unwrap();
+    assert_eq!(loaded.hook.domain, "example.com");
+    assert_eq!(loaded.hook.port, 8443);
+
+    std::fs::remove_file(&backup).unwrap();
+    assert!(AppState::load_from(&path, &backup, None).is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_load_unversioned_state() {
+    // as written by the first release, which saved the state without a version
+    let mut bytes = Vec::new();
+    // hook: port 443, domain, bot token, secret token, certificate and its key
+    bytes.extend([0xBB, 0x03]);
+    for text in ["example.com", "token", "secret", "", ""] {
+        bytes.push(text.len() as u8);
+        bytes.extend(text.as_bytes());
+    }
+    // one instance under the chat -5, in spanish
+    bytes.extend([1, 9, 1]);
+    bytes.push(13);
+    bytes.extend(b"Europe/Madrid");
+    // one person 7, with a span from 1000 to 4600, entered at 4700, named Ana
+    bytes.extend([1, 14]);
+    bytes.extend([1, 0xD0, 0x0F, 0xF0, 0x47]);
+    bytes.extend([1, 0xB8, 0x49]);
+    bytes.extend([1, 3]);
+    bytes.extend(b"Ana");
+    bytes.push(0);
+
+    let state = AppState::from_bytes(&bytes, None).unwrap();
+    assert_eq!(state.hook.port, 443);
+    assert_eq!(state.hook.domain, "example.com");
+    assert_eq!(state.hook.bot_token, "token");
+    assert_eq!(state.hook.secret_token, "secret");
+    let instance = &state.instances[&-5];
+    assert_eq!(instance.language, Language::Es);
+    assert_eq!(instance.time_zone, Tz::Europe__Madrid);
+    assert_eq!(
+        instance.max_open_duration,
+        Instance::DEFAULT_MAX_OPEN_DURATION
+    );
+    assert_eq!(instance.entered(7), Some(4700));
+    assert_eq!(instance.get_name(7).as_deref(), Some("Ana"));
+    assert_eq!(
+        instance.select(7, 0, 10_000),
+        [Span {
+            enter: 1000,
+            leave: 4600
+        }]
+    );
+
+    // saved again in the current layout
+    let saved = state.to_bytes();
+    assert!(saved.starts_with(AppState::MAGIC));
+    let state = AppState::from_bytes(&saved, None).unwrap();
+    assert_eq!(state.instances[&-5].entered(7), Some(4700));
+
+    // trailing bytes are not a state
+    bytes.push(0);
+    assert!(AppState::from_bytes(&bytes, None).is_none());
+}
+
+#[test]
+fn test_encrypted_state() {
+    let key = crate::derive_key(b"passphrase");
+    let state = AppState::new("token".into(), "example.com".into(), 8443).with_key(Some(key));
+    let bytes = state.to_bytes();
+    assert!(!bytes.windows(11).any(|w| w == b"example.com"));
+
+    let loaded = AppState::from_bytes(&bytes, Some(key)).unwrap();
+    assert_eq!(loaded.hook.domain, "example.com");
+    assert_eq!(loaded.hook.cert_key, state.hook.cert_key);
+    // saving again keeps it encrypted
+    assert_eq!(loaded.key, Some(key));
+
+    assert!(AppState::from_bytes(&bytes, Some(crate::derive_key(b"wrong"))).is_none());
+    assert!(AppState::from_bytes(&bytes, None).is_none());
+
+    let plain = AppState::new("token".into(), "example.com".into(), 8443).to_bytes();
+    assert!(plain.windows(11).any(|w| w == b"example.com"));
+    assert!(AppState::from_bytes(&plain, None).is_some());
+
+    // a passphrase set on a state saved in plain
+    let loaded = AppState::from_bytes(&plain, Some(key)).unwrap();
+    assert_eq!(loaded.hook.domain, "example.com");
+    assert_eq!(loaded.key, Some(key));
+    let bytes = loaded.to_bytes();
+    assert!(!bytes.windows(11).any(|w| w == b"example.com"));
+    assert!(AppState::from_bytes(&bytes, Some(key)).is_some());
+}
+
+#[test]
+fn test_persons() {
+    let date = NOW;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    instance.set_first_name(30, "Carla".into());
+    instance.set_first_name(10, "Alice".into());
+    instance.set_first_name(20, "Bob".into());
+    instance.set_admin(10, true);
+
+    instance.command(20, date, Command::Persons, &mut output);
+    let Output::Persons(persons) = &output[1] else {
+        panic!("expected persons, found {:?}", output[1]);
+    };
+    assert_eq!(
+        persons,
+        &[
+            (10, "Alice".to_string()),
+            (20, "Bob".to_string()),
+            (30, "Carla".to_string()),
+        ]
+    );
+
+    // only an admin can remove someone
+    output.clear();
+    let remove = Command::RemovePerson {
+        target: Target::Index(3),
+    };
+    instance.command(20, date, remove.clone(), &mut output);
+    assert!(matches!(output[1], Output::PermissionDenied));
+    assert!(instance.person(30).is_some());
+
+    output.clear();
+    instance.command(10, date, remove, &mut output);
+    assert!(matches!(&output[1], Output::PersonRemoved { person: 30, name } if name == "Carla"));
+    assert!(instance.person(30).is_none());
+    assert_eq!(instance.listed_persons(), [10, 20]);
+
+    output.clear();
+    let remove = Command::RemovePerson {
+        target: Target::Index(3),
+    };
+    instance.command(10, date, remove, &mut output);
+    assert!(matches!(output[1], Output::UnknownPerson));
+}
+
+#[test]
+fn test_set_admin() {
+    let date = NOW;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    instance.with_person(10).with_person(20);
+
+    // without any admin, anyone can appoint one
+    let set_admin = |target, admin| Command::SetAdmin { target, admin };
+    instance.command(10, date, set_admin(Target::Me, true), &mut output);
+    assert!(instance.is_admin(10));
+    assert!(!instance.is_admin(20));
+
+    output.clear();
+    instance.command(20, date, set_admin(Target::Me, true), &mut output);
+    assert!(matches!(output[1], Output::PermissionDenied));
+    assert!(!instance.is_admin(20));
+
+    output.clear();
+    instance.command(10, date, set_admin(Target::Index(2), true), &mut output);
+    assert!(instance.is_admin(20));
+    assert!(matches!(
+        output[..],
+        [
+            Output::Ok,
+            Output::AdminSet {
+                person: 20,
+                admin: true,
+                ..
+            }
+        ]
+    ));
+}
+
+#[test]
+fn test_admin_guard() {
+    let date = NOW;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    instance.set_admin(10, true);
+    instance.with_person(20);
+
+    instance.command(
+        20,
+        date,
+        Command::SetTimeZone {
+            time_zone: Tz::Europe__Paris,
+        },
+        &mut output,
+    );
+    assert!(matches!(output[1], Output::PermissionDenied));
+    assert_eq!(instance.time_zone, Tz::UTC);
+
+    output.clear();
+    instance.command(
+        20,
+        date,
+        Command::SetLanguage {
+            language: Language::Es,
+        },
+        &mut output,
+    );
+    assert!(matches!(output[1], Output::PermissionDenied));
+    assert_eq!(instance.language, Language::En);
+
+    output.clear();
+    instance.command(
+        10,
+        date,
+        Command::SetTimeZone {
+            time_zone: Tz::Europe__Paris,
+        },
+        &mut output,
+    );
+    assert!(matches!(output[0], Output::Ok));
+    assert_eq!(instance.time_zone, Tz::Europe__Paris);
+}
+
+#[test]
+fn test_private_instance() {
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    let text = |text: &str| {
+        let mut input = text_input(42, 42, NOW, text);
+        if let Input::Text { language_code, .. } = &mut input {
+            *language_code = Some("en-GB".into());
+        }
+        input
+    };
+    let mut outputs = Vec::new();
+    for text in [text("enter 9h00"), text("leave 17h00")] {
+        outputs.extend(state.handle(text, NOW).into_iter().map(|(o, _)| o));
+    }
+    assert!(
+        !outputs
+            .iter()
+            .any(|o| matches!(o, Output::YourAreNotPartOfAGroup | Output::Failure))
+    );
+    assert!(outputs.iter().any(|o| matches!(o, Output::SpanAdded(_))));
+    let instance = &state.instances[&42];
+    assert!(instance.is_admin(42));
+    assert_eq!(instance.listed_persons(), [42]);
+    assert_eq!(instance.language, Language::En);
+}
+
+#[test]
+fn test_status() {
+    let date = NOW;
+    let today = 1_699_920_000;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    for (enter, leave) in [
+        // yesterday, not counted
+        (today - 2 * 60 * 60, today - 60 * 60),
+        (today + 8 * 60 * 60, today + 12 * 60 * 60),
+        (today + 13 * 60 * 60, today + 14 * 60 * 60 + 30 * 60),
+    ] {
+        instance.command(1, date, Command::Span { enter, leave }, &mut output);
+    }
+
+    output.clear();
+    instance.command(1, date, Command::Status, &mut output);
+    assert!(matches!(
+        output[1],
+        Output::Status {
+            entered_since: None,
+            today_minutes: 330,
+        }
+    ));
+
+    let enter = today + 22 * 60 * 60;
+    instance.command(1, date, Command::Enter { enter }, &mut output);
+    output.clear();
+    instance.command(1, date, Command::Status, &mut output);
+    assert!(matches!(
+        output[1],
+        Output::Status {
+            entered_since: Some(since),
+            today_minutes: 330,
+        } if since == enter
+    ));
+}
+
+#[test]
+fn test_today() {
+    use crate::output::{Localized, Text};
+    let date = NOW;
+    let today = 1_699_920_000;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    for (enter, leave) in [
+        // yesterday, not listed
+        (today - 2 * 60 * 60, today - 60 * 60),
+        (today + 8 * 60 * 60, today + 12 * 60 * 60),
+        (today + 13 * 60 * 60, today + 14 * 60 * 60 + 30 * 60),
+    ] {
+        instance.command(1, date, Command::Span { enter, leave }, &mut output);
+    }
+    let enter = today + 21 * 60 * 60;
+    instance.command(1, date, Command::Enter { enter }, &mut output);
+
+    output.clear();
+    instance.command(1, date, Command::Today, &mut output);
+    let Output::DaySummary { spans, open } = &output[1] else {
+        panic!("expected a summary, found {:?}", output[1]);
+    };
+    assert_eq!(
+        spans,
+        &[
+            Span {
+                enter: today + 8 * 60 * 60,
+                leave: today + 12 * 60 * 60,
+            },
+            Span {
+                enter: today + 13 * 60 * 60,
+                leave: today + 14 * 60 * 60 + 30 * 60,
+            },
+        ]
+    );
+    assert_eq!(open, &Some(Span { enter, leave: date }));
+
+    let context = Context {
+        chat: 0,
+        thread: None,
+        date,
+        language: Language::En,
+        time_zone: Tz::UTC,
+    };
+    let Text::Markdown(text) = output[1].text(&context) else {
+        panic!("summary should be markdown");
+    };
+    // 5h30 of spans and 1h13 since the entry
+    assert!(text.contains("\\(_1h13_\\)"), "{text}");
+    assert!(text.ends_with("Total: _6h43_"), "{text}");
+}
+
+#[test]
+fn test_duplicate_update() {
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    let input = text_input(42, 42, NOW, "enter 9h00 leave 17h00");
+    let first = state.update(120, input.clone(), NOW);
+    assert!(matches!(
+        first[..],
+        [(Output::Ok, _), (Output::SpanAdded(_), _)]
+    ));
+    assert!(state.update(120, input, NOW).is_empty());
+    let state = AppState::from_bytes(&state.to_bytes(), None).unwrap();
+    assert_eq!(state.processed_updates, [120]);
+}
+
+#[test]
+fn test_display_name() {
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    instance.set_first_name(1, "Alice".into());
+    instance.set_last_name(1, "Smith".into());
+    assert_eq!(instance.get_name(1).as_deref(), Some("Alice Smith"));
+
+    let name = "Ali".to_string();
+    instance.command(1, NOW, Command::SetName { name }, &mut output);
+    assert!(matches!(output[..], [.., Output::Ok]));
+    assert_eq!(instance.get_name(1).as_deref(), Some("Ali"));
+
+    // a profile update keeps the chosen name
+    instance.set_first_name(1, "Alicia".into());
+    assert_eq!(instance.get_name(1).as_deref(), Some("Ali"));
+    assert_eq!(instance.get_name(2), None);
+}
+
+#[test]
+fn test_switch() {
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    let text = |chat: i64, name: Option<&str>, text: &str| {
+        let mut input = text_input(chat, 42, NOW, text);
+        if let Input::Text {
+            title,
+            language_code,
+            ..
+        } = &mut input
+        {
+            *title = name.map(Into::into);
+            *language_code = Some("en".into());
+        }
+        input
+    };
+    let mut outputs = Vec::new();
+    for input in [
+        text(-2, Some("Night shift"), "status"),
+        text(-1, Some("Day shift"), "status"),
+        text(42, None, "switch day shift"),
+        text(42, None, "enter 9h00 leave 17h00"),
+        text(42, None, "switch Night shift"),
+        text(42, None, "enter 18h00 leave 20h00"),
+        text(42, None, "switch Weekend"),
+    ] {
+        outputs.extend(state.handle(input, NOW).into_iter().map(|(o, _)| o));
+    }
+    assert!(matches!(outputs.last(), Some(Output::UnknownGroup)));
+    assert!(!state.instances.contains_key(&42));
+    let minutes = |key: i64| {
+        let mut output = Vec::new();
+        let instance = state.instances.get_mut(&key).unwrap();
+        instance.command(42, NOW, Command::Status, &mut output);
+        match output[..] {
+            [.., Output::Status { today_minutes, .. }] => today_minutes,
+            _ => panic!("expected status, found {output:?}"),
+        }
+    };
+    assert_eq!(minutes(-1), 8 * 60);
+    assert_eq!(minutes(-2), 2 * 60);
+    // the failed switch keeps the previous choice
+    assert_eq!(state.active_instance(42), Some(-2));
+}
+
+#[test]
+fn test_handle() {
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    let text = |text: &str| {
+        let mut input = text_input(-1, 42, NOW, text);
+        if let Input::Text {
+            thread,
+            language_code,
+            ..
+        } = &mut input
+        {
+            *thread = Some(3);
+            *language_code = Some("en".into());
+        }
+        input
+    };
+    let outputs = state.handle(
+        Input::NewGroup {
+            chat: -1,
+            person: 42,
+            name: "Team".into(),
+        },
+        NOW,
+    );
+    assert!(matches!(outputs[..], [(Output::PleasePromoteTheBot, _)]));
+    assert_eq!(outputs[0].1.date, NOW);
+
+    // the group defaults to spanish
+    let outputs = state.handle(text("entra 9h00"), NOW);
+    assert!(matches!(
+        outputs[..],
+        [(Output::Ok, _), (Output::Entered(_), _)]
+    ));
+    let outputs = state.handle(text("sale 17h00"), NOW);
+    assert!(matches!(
+        outputs[..],
+        [(Output::Ok, _), (Output::SpanAdded(_), _)]
+    ));
+    let context = outputs[1].1;
+    assert_eq!((context.chat, context.thread), (-1, Some(3)));
+    assert_eq!(context.language, Language::Es);
+    let outputs = state.handle(text("gibberish"), NOW);
+    assert!(matches!(
+        outputs[..],
+        [(Output::CouldNotRecognizeCommand, _)]
+    ));
+}
+
+#[test]
+fn test_set_hours() {
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    let text = |text: &str| text_input(42, 42, NOW, text);
+    let outputs = state.handle(text("set hours 160"), NOW);
+    assert!(matches!(outputs[..], [(Output::Ok, _)]));
+    assert_eq!(state.instances[&42].contracted_minutes(42), Some(160 * 60));
+    // would overflow once in minutes
+    let outputs = state.handle(text("set hours 71582789"), NOW);
+    assert!(matches!(
+        outputs[..],
+        [(Output::Failure, _), (Output::TooManyHours, _)]
+    ));
+    let outputs = state.handle(text("set hours 745"), NOW);
+    assert!(matches!(
+        outputs[..],
+        [(Output::Failure, _), (Output::TooManyHours, _)]
+    ));
+    assert_eq!(state.instances[&42].contracted_minutes(42), Some(160 * 60));
+    let outputs = state.handle(text("set hours 0"), NOW);
+    assert!(matches!(outputs[..], [(Output::Ok, _)]));
+    assert_eq!(state.instances[&42].contracted_minutes(42), None);
+}
+
+#[test]
+fn test_replay() {
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    let text = |person: i64, text: &str| {
+        let mut input = text_input(-1, person, NOW, text);
+        if let Input::Text { language_code, .. } = &mut input {
+            *language_code = Some("en".into());
+        }
+        input
+    };
+    for input in [
+        text(1, "enter 8h00"),
+        text(2, "enter 9h00 leave 13h00"),
+        text(1, "leave 16h00"),
+        text(1, "set hours 160"),
+        text(1, "clear"),
+        text(1, "confirm clear"),
+        text(2, "name Bea"),
+        text(1, "person @2 admin true"),
+    ] {
+        state.handle(input, NOW);
+    }
+    // the changes come from what the commands report, including those made to other persons
+    let events = state.events();
+    assert_eq!(events.len(), 8);
+    assert!(matches!(
+        events[2].changes[..],
+        [Change::SpanAdded { person: 1, .. }]
+    ));
+    assert!(matches!(
+        events[5].changes[..],
+        [Change::SpanRemoved { person: 1, .. }]
+    ));
+    assert_eq!(
+        events[7].changes,
+        [Change::Admin {
+            person: 2,
+            admin: true
+        }]
+    );
+
+    let mut replayed = AppState::new("token".into(), "example.com".into(), 8443);
+    replayed.replay(events);
+    // hash maps do not serialize in a stable order, the instances are compared by content
+    let summary = |state: &AppState| {
+        let instance = &state.instances[&-1];
+        instance
+            .listed_persons()
+            .into_iter()
+            .map(|person| {
+                (
+                    person,
+                    instance.get_name(person),
+                    instance.is_admin(person),
+                    instance.contracted_minutes(person),
+                    instance.entered(person),
+                    instance.clearable(person, i64::MIN, i64::MAX),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(replayed.instances.len(), state.instances.len());
+    assert_eq!(summary(&replayed), summary(&state));
+    assert!(replayed.events().is_empty());
+}
+
+#[test]
+fn test_event_log() {
+    let dir = std::env::temp_dir().join(format!("fichar-test-events-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let text = |text: &str| text_input(42, 42, NOW, text);
+    for key in [None, Some(crate::derive_key(b"passphrase"))] {
+        let path = dir.join("events.jsonl");
+        let mut state = AppState::new("token".into(), "example.com".into(), 8443).with_key(key);
+        // each save appends the events since the previous one, and forgets them
+        state.handle(text("enter 9h00"), NOW);
+        state.handle(text("leave 17h00"), NOW);
+        state.append_events(&path);
+        assert!(state.events().is_empty());
+        state.append_events(&path);
+        state.handle(text("status"), NOW);
+        state.append_events(&path);
+
+        let events = AppState::read_events(&path, key);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            events[1].changes[..],
+            [Change::SpanAdded { person: 42, .. }]
+        ));
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.windows(5).any(|w| w == b"Eddie"), key.is_none());
+
+        // simulates a crash in the middle of a write
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"now":17"#).unwrap();
+        assert_eq!(AppState::read_events(&path, key).len(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_absence() {
+    use crate::{output::OutputMonth, state::instance::AbsenceKind};
+    use time_util::TimeHintMonth;
+    let date = NOW;
+    let monday = 1_699_833_600;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    instance.command(
+        1,
+        date,
+        Command::Span {
+            enter: date - 4 * 60 * 60,
+            leave: date - 60 * 60,
+        },
+        &mut output,
+    );
+    for (day, kind) in [
+        (
+            TimeHintDay::Weekday(chrono::Weekday::Mon),
+            AbsenceKind::Sick,
+        ),
+        (TimeHintDay::YearMonthDay(2023, 11, 1), AbsenceKind::Holiday),
+    ] {
+        output.clear();
+        instance.command(1, date, Command::AbsenceHint { day, kind }, &mut output);
+        assert!(matches!(
+            output[..],
+            [Output::Ok, Output::AbsenceMarked { .. }]
+        ));
+    }
+
+    output.clear();
+    instance.command(
+        1,
+        date,
+        Command::MonthHint {
+            time_hint: TimeHintMonth::None,
+            format: Some(DocFormat::Png),
+            all: false,
+            time_zone: None,
+        },
+        &mut output,
+    );
+    let Output::Month {
+        spans, absences, ..
+    } = &output[1]
+    else {
+        panic!("expected a document, found {:?}", output[1]);
+    };
+    assert_eq!(
+        absences,
+        &[
+            (1_698_796_800, AbsenceKind::Holiday),
+            (monday, AbsenceKind::Sick),
+        ]
+    );
+
+    let context = Context {
+        chat: 0,
+        thread: None,
+        date,
+        language: Language::En,
+        time_zone: Tz::UTC,
+    };
+    let month = OutputMonth::new(&context, "Eddie".into(), date, false, spans.clone(), None)
+        .with_absences(&context, absences.clone());
+    assert_eq!(month.minutes, 3 * 60);
+    let json = serde_json::to_value(&month).unwrap();
+    assert_eq!(json["absences"][1]["kind"], "sick");
+    assert_eq!(json["absences"][1]["date"]["day"], 13);
+}
+
+#[test]
+fn test_rounding() {
+    use crate::state::instance::RoundingRule;
+    let date = NOW;
+    let today = 1_699_920_000;
+    let at = |hour: i64, minute: i64| today + hour * 60 * 60 + minute * 60;
+    let utc = Tz::UTC;
+    assert_eq!(RoundingRule::Nearest(5).apply(at(18, 7), utc), at(18, 5));
+    assert_eq!(RoundingRule::Nearest(5).apply(at(18, 8), utc), at(18, 10));
+    assert_eq!(RoundingRule::Up(15).apply(at(18, 1), utc), at(18, 15));
+    assert_eq!(RoundingRule::Up(15).apply(at(18, 0), utc), at(18, 0));
+    assert_eq!(RoundingRule::Down(15).apply(at(18, 14), utc), at(18, 0));
+    assert_eq!(RoundingRule::None.apply(at(18, 7), utc), at(18, 7));
+
+    // 2023-11-14 00:00 in Kolkata, at UTC+05:30
+    let kolkata = Tz::Asia__Kolkata;
+    let local = |hour: i64, minute: i64| at(hour, minute) - (5 * 60 + 30) * 60;
+    assert_eq!(
+        RoundingRule::Down(60).apply(local(9, 20), kolkata),
+        local(9, 0)
+    );
+    assert_eq!(
+        RoundingRule::Nearest(60).apply(local(9, 20), kolkata),
+        local(9, 0)
+    );
+    assert_eq!(
+        RoundingRule::Nearest(60).apply(local(9, 40), kolkata),
+        local(10, 0)
+    );
+    assert_eq!(
+        RoundingRule::Up(60).apply(local(9, 0), kolkata),
+        local(9, 0)
+    );
+    assert_eq!(
+        RoundingRule::Up(60).apply(local(9, 1), kolkata),
+        local(10, 0)
+    );
+    let mut instance = Instance::new(Language::En, kolkata);
+    let mut output = Vec::new();
+    let rounding = RoundingRule::Down(60);
+    instance.command(1, date, Command::SetRounding { rounding }, &mut output);
+    instance.command(
+        1,
+        date,
+        Command::Enter {
+            enter: local(9, 20),
+        },
+        &mut output,
+    );
+    assert_eq!(instance.entered(1), Some(local(9, 0)));
+
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    let rounding = RoundingRule::Nearest(5);
+    instance.command(1, date, Command::SetRounding { rounding }, &mut output);
+    output.clear();
+    instance.command(1, date, Command::Enter { enter: at(18, 7) }, &mut output);
+    assert_eq!(instance.entered(1), Some(at(18, 5)));
+    assert!(matches!(
+        &output[..],
+        [Output::Ok, Output::Entered(enter), Output::Rounded(rounded)]
+            if *enter == at(18, 5) && rounded == &[(at(18, 7), at(18, 5))]
+    ));
+
+    // already aligned times are not reported
+    output.clear();
+    instance.command(1, date, Command::Leave { leave: at(20, 0) }, &mut output);
+    assert!(matches!(output[..], [Output::Ok, Output::SpanAdded(_)]));
+}
+
+#[test]
+fn test_output_closed() {
+    use std::cell::Cell;
+    use tokio::sync::mpsc;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let state = AppState::new("token".into(), "example.com".into(), 8443);
+    let (i_sender, i_receiver) = mpsc::channel(8);
+    let (o_sender, o_receiver) = mpsc::channel(8);
+    drop(o_receiver);
+    let text = |text: &str| text_input(42, 42, NOW, text);
+    i_sender.try_send((1, text("enter 9h00"))).unwrap();
+    i_sender.try_send((2, text("leave 17h00"))).unwrap();
+    drop(i_sender);
+    let saves = Cell::new(0);
+    let state = runtime
+        .block_on(state.process_inputs_with(i_receiver, o_sender, |_| saves.set(saves.get() + 1)));
+    assert_eq!(saves.get(), 1);
+    // the input received after the failure is still applied
+    assert_eq!(state.events().len(), 2);
+    assert_eq!(
+        state.instances[&42].clearable(42, i64::MIN, i64::MAX).len(),
+        1
+    );
+}
+
+#[test]
+fn test_debounced_save() {
+    use std::cell::Cell;
+    use tokio::sync::mpsc;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    state.save_debounce = Duration::from_millis(10);
+    let (i_sender, i_receiver) = mpsc::channel(8);
+    let (o_sender, mut o_receiver) = mpsc::channel(8);
+    let input = text_input(42, 42, NOW, "enter 9h00");
+    let saved = Cell::new(None);
+    let processor = state.process_inputs_with(i_receiver, o_sender, |state| {
+        saved.set(Some(state.instances[&42].entered(42)))
+    });
+    let inputs = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // nothing changed, nothing to save
+        assert_eq!(saved.get(), None);
+        i_sender.send((1, input)).await.unwrap();
+        while o_receiver.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(matches!(saved.get(), Some(Some(_))));
+        drop(i_sender);
+    };
+    runtime.block_on(async { tokio::join!(processor, inputs) });
+}
+
+#[test]
+fn test_export_round_trip() {
+    let mut state = AppState::new("123:bot-token".into(), "example.com".into(), 8443);
+    let text = |chat: i64, person: i64, text: &str| {
+        let mut input = text_input(chat, person, NOW, text);
+        if let Input::Text {
+            user,
+            title,
+            language_code,
+            ..
+        } = &mut input
+        {
+            *user = (Some(format!("Person {person}")), Some("Smith".into()));
+            *title = (chat < 0).then(|| format!("Team {chat}"));
+            *language_code = Some("es".into());
+        }
+        input
+    };
+    for (id, (chat, person, message)) in [
+        (42, 42, "entra 9h00 sale 17h00"),
+        (43, 43, "entra 8h00 sale 15h00"),
+        (-1, 42, "entra 10h00 sale 18h00"),
+        (-1, 43, "entra 9h30"),
+        (-1, 44, "entra 7h00 sale 14h00"),
+        (-2, 44, "entra 12h00 sale 20h00"),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        state.update(id as u64, text(chat, person, message), NOW);
+    }
+    assert_eq!(state.instances(), 4);
+
+    let exported = state.export(false);
+    assert!(!exported.contains("123:bot-token"));
+    assert!(!exported.contains(&state.hook.secret_token));
+    assert!(!exported.contains("PRIVATE KEY"));
+    let mut imported = AppState::import(&exported).unwrap();
+    assert!(imported.hook.is_redacted());
+    imported.hook.restore_secrets(state.hook.clone());
+    assert!(!imported.hook.is_redacted());
+    assert_eq!(imported.hook.bot_token, state.hook.bot_token);
+    assert_eq!(imported.hook.secret_token, state.hook.secret_token);
+    assert_eq!(imported.hook.cert_key, state.hook.cert_key);
+
+    // hash maps of several entries do not serialize in a stable order, compare as JSON values
+    let value = |text: &str| serde_json::from_str::<serde_json::Value>(text).unwrap();
+    let exported = state.export(true);
+    assert!(exported.contains("123:bot-token"));
+    let imported = AppState::import(&exported).unwrap();
+    assert!(!imported.hook.is_redacted());
+    assert_eq!(value(&imported.export(true)), value(&exported));
+    assert_eq!(value(&imported.export(false)), value(&state.export(false)));
+    assert!(AppState::import("{").is_err());
+}
+
+#[test]
+fn test_enter_date() {
+    use time_util::TimeHintMinute;
+    // 2023-11-14, a tuesday
+    let today = 1_699_920_000;
+    let date = today + 9 * 60 * 60;
+    let day = 24 * 60 * 60;
+    let time_hint = TimeHintMinute::HourMinute(18, 30);
+
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    let day_hint = Some(TimeHintDay::Weekday(chrono::Weekday::Fri));
+    instance.command(
+        1,
+        date,
+        Command::EnterHint {
+            day: day_hint,
+            time_hint,
+        },
+        &mut output,
+    );
+    let friday = today - 4 * day + 18 * 60 * 60 + 30 * 60;
+    assert_eq!(instance.entered(1), Some(friday));
+
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let day_hint = Some(TimeHintDay::Yesterday);
+    instance.command(
+        1,
+        date,
+        Command::EnterHint {
+            day: day_hint,
+            time_hint,
+        },
+        &mut output,
+    );
+    let yesterday = today - day + 18 * 60 * 60 + 30 * 60;
+    assert_eq!(instance.entered(1), Some(yesterday));
+    instance.command(
+        1,
+        date,
+        Command::LeaveHint {
+            day: day_hint,
+            time_hint: TimeHintMinute::HourMinute(20, 0),
+        },
+        &mut output,
+    );
+    assert_eq!(
+        instance.clearable(1, i64::MIN, i64::MAX),
+        [Span {
+            enter: yesterday,
+            leave: yesterday + 90 * 60,
+        }]
+    );
+}
+
+#[test]
+fn test_week_start() {
+    use crate::output::OutputMonth;
+    use time_util::{TimeHintWeek, WeekStart};
+    // 2023-11-12 10:00:00 UTC, a sunday
+    let sunday = 1_699_747_200;
+    let date = sunday + 10 * 60 * 60;
+    let day = 24 * 60 * 60;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    let week = |instance: &mut Instance, output: &mut Vec<Output>| {
+        output.clear();
+        let time_hint = TimeHintWeek::None;
+        let (format, all, time_zone) = (None, false, None);
+        let command = Command::WeekHint {
+            time_hint,
+            format,
+            all,
+            time_zone,
+        };
+        instance.command(1, date, command, output);
+        match output[1] {
+            Output::Month { month, .. } => month,
+            _ => panic!("expected a document, found {:?}", output[1]),
+        }
+    };
+    assert_eq!(week(&mut instance, &mut output), sunday - 6 * day);
+
+    let week_start = WeekStart::Sunday;
+    instance.command(1, date, Command::SetWeekStart { week_start }, &mut output);
+    let start = week(&mut instance, &mut output);
+    assert_eq!(start, sunday);
+
+    // numbered after the following ISO week
+    let context = Context {
+        chat: 0,
+        thread: None,
+        date,
+        language: Language::En,
+        time_zone: Tz::UTC,
+    };
+    let month = OutputMonth::new(&context, "Eddie".into(), start, true, Vec::new(), None);
+    assert_eq!(month.week, Some(46));
+}
+
+#[test]
+fn test_rate_limit() {
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    state.rate_limit = RateLimit {
+        burst: 5,
+        interval: 10,
+    };
+    let input = |chat, person| text_input(chat, person, NOW, "help");
+    let mut slow_downs = 0;
+    for update_id in 0..100 {
+        for (output, _) in state.update(update_id, input(42, 42), NOW) {
+            if let Output::SlowDown = output {
+                slow_downs += 1;
+            }
+        }
+    }
+    assert_eq!(state.events().len(), 5);
+    assert_eq!(slow_downs, 1);
+
+    // another chat has its own bucket
+    state.update(100, input(43, 43), NOW);
+    assert_eq!(state.events().len(), 6);
+    // and so has another person of the same chat
+    let outputs = state.update(200, input(42, 44), NOW);
+    assert!(!matches!(outputs[..], [(Output::SlowDown, _)]));
+    assert_eq!(state.events().len(), 7);
+
+    // two messages regained after two intervals, then warned again once
+    let mut slow_downs = 0;
+    for update_id in 101..110 {
+        for (output, _) in state.update(update_id, input(42, 42), NOW + 25) {
+            if let Output::SlowDown = output {
+                slow_downs += 1;
+            }
+        }
+    }
+    assert_eq!(state.events().len(), 9);
+    assert_eq!(slow_downs, 1);
+}
+
+#[test]
+fn test_format() {
+    use time_util::TimeHintMonth;
+    let date = NOW;
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    let mut output = Vec::new();
+    let mut month = |instance: &mut Instance, format| {
+        output.clear();
+        let time_hint = TimeHintMonth::None;
+        let (all, time_zone) = (false, None);
+        let command = Command::MonthHint {
+            time_hint,
+            format,
+            all,
+            time_zone,
+        };
+        instance.command(1, date, command, &mut output);
+        match output[1] {
+            Output::Month { format, .. } => format,
+            _ => panic!("expected a document, found {:?}", output[1]),
+        }
+    };
+    assert_eq!(month(&mut instance, None), DocFormat::Png);
+    assert_eq!(month(&mut instance, Some(DocFormat::Pdf)), DocFormat::Pdf);
+
+    let format = DocFormat::Pdf;
+    instance.command(1, date, Command::SetFormat { format }, &mut Vec::new());
+    assert_eq!(month(&mut instance, None), DocFormat::Pdf);
+    assert_eq!(month(&mut instance, Some(DocFormat::Png)), DocFormat::Png);
+    // the preference is per person
+    assert_eq!(instance.format(2), DocFormat::Png);
+}
+
+#[test]
+fn test_display_zone() {
+    use crate::output::OutputMonth;
+    use time_util::TimeHintMonth;
+    let date = NOW;
+    // 2023-11-14 08:00:00 UTC
+    let enter = 1_699_948_800;
+    let mut instance = Instance::new(Language::En, Tz::Europe__Madrid);
+    let mut output = Vec::new();
+    let leave = enter + 8 * 60 * 60;
+    instance.command(1, date, Command::Span { enter, leave }, &mut output);
+
+    let mut hours = |time_zone| {
+        output.clear();
+        let command = Command::MonthHint {
+            time_hint: TimeHintMonth::None,
+            format: None,
+            all: false,
+            time_zone,
+        };
+        instance.command(1, date, command, &mut output);
+        let Output::Month {
+            month,
+            ref spans,
+            time_zone,
+            ..
+        } = output[1]
+        else {
+            panic!("expected a document, found {:?}", output[1]);
+        };
+        let context = Context {
+            chat: 0,
+            thread: None,
+            date,
+            language: Language::En,
+            time_zone,
+        };
+        let month = OutputMonth::new(&context, "Eddie".into(), month, false, spans.clone(), None);
+        (month.spans[0].enter.hour, month.spans[0].leave.hour)
+    };
+    assert_eq!(hours(None), (9, 17));
+    assert_eq!(hours(Some(Tz::UTC)), (8, 16));
+    assert_eq!(hours(Some(Tz::America__New_York)), (3, 11));
+    // shown in another zone, the instance keeps its own
+    assert_eq!(instance.time_zone, Tz::Europe__Madrid);
+}
+
+#[test]
+fn test_edited_command() {
+    let mut state = AppState::new("token".into(), "example.com".into(), 8443);
+    let date = NOW;
+    // the personal instance is in Madrid, an hour ahead of UTC
+    let today = 1_699_920_000 - 60 * 60;
+    let text = |text: &str, correction| {
+        let mut input = text_input(42, 42, date, text);
+        if let Input::Text { edited, .. } = &mut input {
+            *edited = correction;
+        }
+        input
+    };
+    state.update(1, text("enter 18h0", false), date);
+    let entered = |state: &AppState| state.instances[&42].entered(42);
+    assert_eq!(entered(&state), Some(today + 18 * 60 * 60));
+
+    // the edit comes as another update, with the date of the original message
+    let outputs = state.update(2, text("enter 18h30", true), date + 60);
+    assert!(matches!(
+        outputs[..],
+        [
+            (Output::Ok, _),
+            (Output::Entered(_), _),
+            (Output::EnterOverrodeEntered(_), _)
+        ]
+    ));
+    assert_eq!(entered(&state), Some(today + 18 * 60 * 60 + 30 * 60));
+
+    // an edited leave is not run again, the person already left
+    state.update(3, text("leave 20h00", false), date + 120);
+    let outputs = state.update(4, text("leave 21h00", true), date + 180);
+    assert!(matches!(outputs[..], [(Output::EditIgnored, _)]));
+    assert_eq!(entered(&state), None);
+    assert_eq!(
+        state.instances[&42].select(42, today, today + 24 * 60 * 60),
+        [Span {
+            enter: today + 18 * 60 * 60 + 30 * 60,
+            leave: today + 20 * 60 * 60,
+        }]
+    );
+}
+
+#[test]
+fn test_time_zones() {
+    let mut instance = Instance::new(Language::Es, Tz::UTC);
+    let mut output = Vec::new();
+    instance.command(1, NOW, Command::TimeZones, &mut output);
+    let Output::TimeZones(time_zones) = &output[1] else {
+        panic!("expected the time zones, found {:?}", output[1]);
+    };
+    assert!(!time_zones.is_empty());
+    assert!(time_zones.contains(&("Londres", Tz::Europe__London)));
+    assert!(time_zones.contains(&("Nueva York", Tz::America__New_York)));
+}
@@ -1,14 +1,36 @@
 use crate::language::Language;
+use chrono::Offset;
 use chrono_tz::Tz;
+use render::DocFormat;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, ops::Range};
-use time_util::TimeZoneExt;
+use time_util::{TimeZoneExt, WeekStart};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Instance {
+    /// Title of the group, personal instances have none
+    pub name: Option<String>,
     pub language: Language,
     pub time_zone: Tz,
+    /// Seconds after which an entry without leave is considered forgotten
+    pub max_open_duration: i64,
+    /// Seconds after its enter at which a forgotten entry is closed
+    pub auto_close_duration: i64,
+    /// Applied to the enter and leave times as they are recorded
+    pub rounding: RoundingRule,
+    pub week_start: WeekStart,
+    /// Breaks up to this long between two spans are not breaks, the spans are joined
+    pub merge_gap_minutes: u32,
     persons: HashMap<i64, Person>,
+    /// Clear requests waiting for confirmation, not worth persisting
+    #[serde(skip)]
+    pending_clears: HashMap<i64, PendingClear>,
+}
+
+#[derive(Debug, Clone)]
+struct PendingClear {
+    day: Range<i64>,
+    requested: i64,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -17,6 +39,68 @@ pub struct Person {
     entered: Option<i64>,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
+    pub display_name: Option<String>,
+    pub contracted_minutes_per_month: Option<u32>,
+    pub admin: bool,
+    /// Days off, by the start of the day, in chronological order
+    pub absences: Vec<(i64, AbsenceKind)>,
+    /// Format of the documents when the command does not give one
+    pub format: DocFormat,
+}
+
+impl From<super::legacy::Instance> for Instance {
+    fn from(legacy: super::legacy::Instance) -> Self {
+        let persons = legacy
+            .persons
+            .into_iter()
+            .map(|(key, person)| {
+                let person = Person {
+                    spans: person.spans,
+                    entered: person.entered,
+                    first_name: person.first_name,
+                    last_name: person.last_name,
+                    ..Person::default()
+                };
+                (key, person)
+            })
+            .collect();
+        Self {
+            persons,
+            ..Self::new(legacy.language, legacy.time_zone)
+        }
+    }
+}
+
+/// Rounding of recorded times to a multiple of some minutes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoundingRule {
+    None,
+    Nearest(u32),
+    Up(u32),
+    Down(u32),
+}
+
+impl RoundingRule {
+    /// Rounds on the clock of the time zone, whose offset need not be whole hours
+    pub fn apply(self, instant: i64, time_zone: Tz) -> i64 {
+        let (step, offset) = match self {
+            Self::None | Self::Nearest(0) | Self::Up(0) | Self::Down(0) => return instant,
+            Self::Nearest(minutes) => (minutes as i64 * 60, minutes as i64 * 30),
+            Self::Up(minutes) => (minutes as i64 * 60, minutes as i64 * 60 - 1),
+            Self::Down(minutes) => (minutes as i64 * 60, 0),
+        };
+        let local = time_zone.instant(instant).offset().fix().local_minus_utc() as i64;
+        (instant + local + offset).div_euclid(step) * step - local
+    }
+}
+
+/// Reason for a day off, told apart from a day simply without spans
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AbsenceKind {
+    Holiday,
+    Sick,
+    Vacation,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -26,18 +110,34 @@ pub struct Span {
 }
 
 impl Instance {
+    /// Seconds a clear request stays open for confirmation
+    pub const CLEAR_CONFIRMATION_TIMEOUT: i64 = 5 * 60;
+    pub const DEFAULT_MAX_OPEN_DURATION: i64 = 16 * 60 * 60;
+    pub const DEFAULT_AUTO_CLOSE_DURATION: i64 = 8 * 60 * 60;
+    /// Longest month, a contract may not ask for more
+    pub const MAX_MINUTES_PER_MONTH: u32 = 31 * 24 * 60;
     pub fn new_spain() -> Self {
         Self::new(Language::Es, Tz::Europe__Madrid)
     }
     pub fn new(language: Language, time_zone: Tz) -> Self {
         Self {
+            name: None,
             language,
             time_zone,
+            max_open_duration: Self::DEFAULT_MAX_OPEN_DURATION,
+            auto_close_duration: Self::DEFAULT_AUTO_CLOSE_DURATION,
+            rounding: RoundingRule::None,
+            week_start: WeekStart::Monday,
+            merge_gap_minutes: 0,
             persons: HashMap::new(),
+            pending_clears: HashMap::new(),
         }
     }
     pub fn get_name(&self, person: i64) -> Option<String> {
         let person = self.person(person)?;
+        if let Some(ref display_name) = person.display_name {
+            return Some(display_name.clone());
+        }
         let mut names = Vec::new();
         if let Some(ref first_name) = person.first_name {
             names.push(first_name.as_str());
@@ -57,6 +157,26 @@ impl Instance {
     pub fn set_last_name(&mut self, person: i64, last_name: String) {
         self.persons.entry(person).or_default().last_name = Some(last_name);
     }
+    pub fn set_display_name(&mut self, person: i64, display_name: String) {
+        self.persons.entry(person).or_default().display_name = Some(display_name);
+    }
+    pub fn set_format(&mut self, person: i64, format: DocFormat) {
+        self.persons.entry(person).or_default().format = format;
+    }
+    pub fn format(&self, person: i64) -> DocFormat {
+        self.person(person)
+            .map(|person| person.format)
+            .unwrap_or_default()
+    }
+    pub fn set_contracted_minutes(&mut self, person: i64, minutes: Option<u32>) {
+        self.persons
+            .entry(person)
+            .or_default()
+            .contracted_minutes_per_month = minutes;
+    }
+    pub fn contracted_minutes(&self, person: i64) -> Option<u32> {
+        self.person(person)?.contracted_minutes_per_month
+    }
     pub fn with_person(&mut self, person: i64) -> &mut Self {
         self.persons.entry(person).or_default();
         self
@@ -64,25 +184,86 @@ impl Instance {
     pub fn person(&self, person: i64) -> Option<&Person> {
         self.persons.get(&person)
     }
+    /// Persons sorted the way targets index them
+    pub fn listed_persons(&self) -> Vec<i64> {
+        let mut persons: Vec<i64> = self.persons().collect();
+        persons.sort_unstable();
+        persons
+    }
+    /// An instance without any admin can be managed by anyone
+    pub fn is_admin(&self, person: i64) -> bool {
+        self.person(person).is_some_and(|p| p.admin) || !self.persons.values().any(|p| p.admin)
+    }
+    pub fn set_admin(&mut self, person: i64, admin: bool) {
+        self.persons.entry(person).or_default().admin = admin;
+    }
     pub fn remove_person(&mut self, person: i64) {
         self.persons.remove(&person);
     }
+    /// Records the span, replacing the ones it overlaps, which are given back along with
+    /// the span recorded: it is joined with the spans within the merge gap of it
     pub fn add_span(
         &mut self,
         person: i64,
         enter: i64,
         leave: i64,
-    ) -> Result<Vec<Span>, AddSpanError> {
-        let span = Span { enter, leave };
+    ) -> Result<(Span, Vec<Span>), AddSpanError> {
+        let mut span = Span { enter, leave };
         if span.enter >= span.leave {
             return Err(AddSpanError::LeaveEarlierThanEnter(span));
         }
+        let gap = i64::from(self.merge_gap_minutes) * 60;
         let person = self.persons.entry(person).or_insert(Person::default());
-        let min = person.spans.partition_point(|s| s.leave <= enter);
+        let mut min = person.spans.partition_point(|s| s.leave <= enter);
         let max = person.spans.partition_point(|s| s.enter < leave);
         let removed = person.spans.drain(min..max).collect();
+        if gap > 0 {
+            if let Some(next) = person.spans.get(min).filter(|s| s.enter - leave <= gap) {
+                span.leave = next.leave;
+                person.spans.remove(min);
+            }
+            let prev = min.checked_sub(1).map(|index| person.spans[index]);
+            if let Some(prev) = prev.filter(|s| enter - s.leave <= gap) {
+                span.enter = prev.enter;
+                min -= 1;
+                person.spans.remove(min);
+            }
+        }
         person.spans.insert(min, span);
-        Ok(removed)
+        debug_assert!(
+            person.is_consistent(),
+            "inconsistent spans {:?}",
+            person.spans
+        );
+        Ok((span, removed))
+    }
+    /// Like `add_span`, but the spans overlapping or touching the new one are merged with it
+    /// instead of discarded, gives the resulting span along with the merged away ones
+    pub fn add_span_merge(
+        &mut self,
+        person: i64,
+        enter: i64,
+        leave: i64,
+    ) -> Result<(Span, Vec<Span>), AddSpanError> {
+        let mut span = Span { enter, leave };
+        if span.enter >= span.leave {
+            return Err(AddSpanError::LeaveEarlierThanEnter(span));
+        }
+        let person = self.persons.entry(person).or_insert(Person::default());
+        let min = person.spans.partition_point(|s| s.leave < enter);
+        let max = person.spans.partition_point(|s| s.enter <= leave);
+        let merged: Vec<Span> = person.spans.drain(min..max).collect();
+        if let (Some(first), Some(last)) = (merged.first(), merged.last()) {
+            span.enter = span.enter.min(first.enter);
+            span.leave = span.leave.max(last.leave);
+        }
+        person.spans.insert(min, span);
+        debug_assert!(
+            person.is_consistent(),
+            "inconsistent spans {:?}",
+            person.spans
+        );
+        Ok((span, merged))
     }
     pub fn enter(&mut self, person: i64, enter: i64) -> Option<i64> {
         let person = self.persons.entry(person).or_insert(Person::default());
@@ -95,12 +276,39 @@ impl Instance {
         let Some(enter) = person_obj.entered.take() else {
             return Err(LeaveError::NotEntered);
         };
-        match self.add_span(person, enter, leave) {
-            Ok(overriden) => Ok((Span { enter, leave }, overriden)),
-            Err(AddSpanError::LeaveEarlierThanEnter(span)) => {
-                Err(LeaveError::LeaveEarlierThanEnter(span))
-            }
+        self.add_span(person, enter, leave)
+            .map_err(|AddSpanError::LeaveEarlierThanEnter(span)| {
+                LeaveError::LeaveEarlierThanEnter(span)
+            })
+    }
+    /// Closes the entry of the person if it was left open for too long, gives the closed span
+    /// along with the spans it overrode
+    pub fn auto_close(&mut self, person: i64, date: i64) -> Option<(Span, Vec<Span>)> {
+        let enter = self.entered(person)?;
+        if date - enter <= self.max_open_duration {
+            return None;
         }
+        let leave = enter + self.auto_close_duration.min(self.max_open_duration);
+        self.leave(person, leave).ok()
+    }
+    /// Marks the day starting at `day` as an absence, replacing the kind it had
+    pub fn set_absence(&mut self, person: i64, day: i64, kind: AbsenceKind) {
+        let absences = &mut self.persons.entry(person).or_default().absences;
+        match absences.binary_search_by_key(&day, |(day, _)| *day) {
+            Ok(index) => absences[index].1 = kind,
+            Err(index) => absences.insert(index, (day, kind)),
+        }
+    }
+    pub fn absences(&self, person: i64, start: i64, end: i64) -> Vec<(i64, AbsenceKind)> {
+        let Some(person) = self.persons.get(&person) else {
+            return Vec::new();
+        };
+        person
+            .absences
+            .iter()
+            .copied()
+            .filter(|(day, _)| (start..end).contains(day))
+            .collect()
     }
     pub fn entered(&self, person: i64) -> Option<i64> {
         self.persons.get(&person)?.entered
@@ -117,20 +325,61 @@ impl Instance {
             .iter()
             .filter_map(move |span| span.conjunction(start..end))
     }
+    /// Entries of every person, persons come in no particular order
+    pub fn all_entries(&self, start: i64, end: i64) -> impl Iterator<Item = (i64, Span)> {
+        self.persons.keys().flat_map(move |&person| {
+            self.entries(person, start, end)
+                .map(move |span| (person, span))
+        })
+    }
+    /// Spans that `clear` would remove
+    pub fn clearable(&self, person: i64, start: i64, end: i64) -> Vec<Span> {
+        if let Some(person) = self.persons.get(&person) {
+            let min = person.spans.partition_point(|s| s.leave <= start);
+            let max = person.spans.partition_point(|s| s.enter < end);
+            person.spans[min..max].to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+    pub fn request_clear(&mut self, person: i64, day: Range<i64>, date: i64) {
+        self.pending_clears.insert(
+            person,
+            PendingClear {
+                day,
+                requested: date,
+            },
+        );
+    }
+    /// Takes the pending clear request of the person, if it has not expired
+    pub fn take_pending_clear(&mut self, person: i64, date: i64) -> Option<Range<i64>> {
+        let pending = self.pending_clears.remove(&person)?;
+        (date - pending.requested <= Self::CLEAR_CONFIRMATION_TIMEOUT).then_some(pending.day)
+    }
     pub fn clear(&mut self, person: i64, start: i64, end: i64) -> Vec<Span> {
         if let Some(person) = self.persons.get_mut(&person) {
             let min = person.spans.partition_point(|s| s.leave <= start);
             let max = person.spans.partition_point(|s| s.enter < end);
-            person.spans.drain(min..max).collect()
+            let removed = person.spans.drain(min..max).collect();
+            debug_assert!(
+                person.is_consistent(),
+                "inconsistent spans {:?}",
+                person.spans
+            );
+            removed
         } else {
             Vec::new()
         }
     }
     pub fn select(&self, person: i64, start: i64, end: i64) -> Vec<Span> {
+        self.select_in(person, start, end, self.time_zone)
+    }
+    /// Like `select`, with the spans split on the days of another time zone
+    pub fn select_in(&self, person: i64, start: i64, end: i64, time_zone: Tz) -> Vec<Span> {
         let mut spans = Vec::new();
         for span in self.entries(person, start, end) {
             spans.extend(
-                self.time_zone
+                time_zone
                     .split_span_on_day(span.enter..span.leave)
                     .map(|range| Span {
                         enter: range.start,
@@ -143,6 +392,42 @@ impl Instance {
     pub fn persons(&self) -> impl Iterator<Item = i64> {
         self.persons.keys().copied()
     }
+    /// Spans breaking the invariants `add_span` maintains
+    pub fn validate(&self) -> Vec<Inconsistency> {
+        let mut inconsistencies = Vec::new();
+        for person in self.listed_persons() {
+            let spans = &self.persons[&person].spans;
+            for &span in spans {
+                if span.enter >= span.leave {
+                    inconsistencies.push(Inconsistency::EmptySpan { person, span });
+                }
+            }
+            for pair in spans.windows(2) {
+                if pair[1].enter < pair[0].leave {
+                    inconsistencies.push(Inconsistency::Overlapping {
+                        person,
+                        first: pair[0],
+                        second: pair[1],
+                    });
+                }
+            }
+        }
+        inconsistencies
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inconsistency {
+    EmptySpan {
+        person: i64,
+        span: Span,
+    },
+    /// Also reported when spans are out of order
+    Overlapping {
+        person: i64,
+        first: Span,
+        second: Span,
+    },
 }
 
 pub enum AddSpanError {
@@ -153,8 +438,20 @@ pub enum LeaveError {
     LeaveEarlierThanEnter(Span),
 }
 
+impl Person {
+    /// Spans are not empty, sorted and disjoint
+    fn is_consistent(&self) -> bool {
+        self.spans.iter().all(|span| span.enter < span.leave)
+            && self
+                .spans
+                .windows(2)
+                .all(|pair| pair[0].leave <= pair[1].enter)
+    }
+}
+
 impl Span {
-    fn conjunction(self, range: Range<i64>) -> Option<Self> {
+    /// Part of the span within the range, `None` if they do not overlap
+    pub fn conjunction(self, range: Range<i64>) -> Option<Self> {
         let selected = Self {
             enter: self.enter.max(range.start),
             leave: self.leave.min(range.end),
@@ -165,3 +462,249 @@ impl Span {
         (self.leave - self.enter) as u32 / 60
     }
 }
+
+#[test]
+fn test_validate() {
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    instance.add_span(1, 100, 200).ok();
+    instance.add_span(1, 300, 400).ok();
+    instance.add_span(2, 100, 200).ok();
+    assert_eq!(instance.validate(), []);
+
+    let first = Span {
+        enter: 100,
+        leave: 200,
+    };
+    let second = Span {
+        enter: 150,
+        leave: 150,
+    };
+    instance.persons.get_mut(&2).unwrap().spans.push(second);
+    assert_eq!(
+        instance.validate(),
+        [
+            Inconsistency::EmptySpan {
+                person: 2,
+                span: second
+            },
+            Inconsistency::Overlapping {
+                person: 2,
+                first,
+                second
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_add_span_consistency() {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..100 {
+        let mut instance = Instance::new(Language::En, Tz::UTC);
+        for _ in 0..50 {
+            let enter = rng.random_range(0..1000);
+            let leave = enter + rng.random_range(-10..100);
+            let before = instance.clearable(1, i64::MIN, i64::MAX);
+            match instance.add_span(1, enter, leave) {
+                Ok((_, removed)) => {
+                    // the removed spans are exactly the ones overlapping the new one
+                    for span in &before {
+                        let overlaps = span.enter < leave && enter < span.leave;
+                        assert_eq!(removed.contains(span), overlaps);
+                    }
+                }
+                Err(AddSpanError::LeaveEarlierThanEnter(_)) => assert!(leave <= enter),
+            }
+            if rng.random_bool(0.1) {
+                let start = rng.random_range(0..1000);
+                instance.clear(1, start, start + rng.random_range(0..200));
+            }
+            if let Some(person) = instance.persons.get(&1) {
+                assert!(person.is_consistent());
+            }
+            assert_eq!(instance.validate(), []);
+        }
+    }
+}
+
+#[test]
+fn test_add_span_tail() {
+    let hour = 60 * 60;
+    let existing = Span {
+        enter: 19 * hour,
+        leave: 21 * hour,
+    };
+
+    // replacing drops the tail of the existing span
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    instance.add_span(1, existing.enter, existing.leave).ok();
+    let Ok((_, removed)) = instance.add_span(1, 18 * hour, 20 * hour) else {
+        panic!("valid span");
+    };
+    assert_eq!(removed, [existing]);
+    assert_eq!(
+        instance.clearable(1, i64::MIN, i64::MAX),
+        [Span {
+            enter: 18 * hour,
+            leave: 20 * hour
+        }]
+    );
+
+    // merging keeps it
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    instance.add_span(1, existing.enter, existing.leave).ok();
+    let adjacent = Span {
+        enter: 21 * hour,
+        leave: 22 * hour,
+    };
+    instance.add_span(1, adjacent.enter, adjacent.leave).ok();
+    let Ok((span, merged)) = instance.add_span_merge(1, 18 * hour, 20 * hour) else {
+        panic!("valid span");
+    };
+    assert_eq!(merged, [existing]);
+    let expected = Span {
+        enter: 18 * hour,
+        leave: 21 * hour,
+    };
+    assert_eq!(span, expected);
+    assert_eq!(
+        instance.clearable(1, i64::MIN, i64::MAX),
+        [expected, adjacent]
+    );
+
+    // touching spans are merged too
+    let Ok((span, merged)) = instance.add_span_merge(1, 21 * hour, 21 * hour + 1) else {
+        panic!("valid span");
+    };
+    assert_eq!(merged, [expected, adjacent]);
+    assert_eq!(
+        span,
+        Span {
+            enter: 18 * hour,
+            leave: 22 * hour
+        }
+    );
+}
+
+#[test]
+fn test_conjunction() {
+    let span = Span {
+        enter: 100,
+        leave: 200,
+    };
+    assert_eq!(span.conjunction(0..300), Some(span));
+    assert_eq!(
+        span.conjunction(120..180),
+        Some(Span {
+            enter: 120,
+            leave: 180
+        })
+    );
+    assert_eq!(span.conjunction(300..400), None);
+    assert_eq!(span.conjunction(0..50), None);
+    assert_eq!(span.conjunction(200..300), None);
+    assert_eq!(span.conjunction(0..100), None);
+    assert_eq!(
+        span.conjunction(150..300),
+        Some(Span {
+            enter: 150,
+            leave: 200
+        })
+    );
+    assert_eq!(
+        span.conjunction(0..150),
+        Some(Span {
+            enter: 100,
+            leave: 150
+        })
+    );
+}
+
+#[test]
+fn test_all_entries() {
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    instance.add_span(1, 100, 200).unwrap();
+    instance.add_span(1, 300, 400).unwrap();
+    instance.add_span(2, 150, 350).unwrap();
+    instance.add_span(2, 500, 600).unwrap();
+    let mut entries: Vec<_> = instance.all_entries(180, 500).collect();
+    entries.sort_by_key(|&(person, span)| (person, span.enter));
+    assert_eq!(
+        entries,
+        [
+            (
+                1,
+                Span {
+                    enter: 180,
+                    leave: 200
+                }
+            ),
+            (
+                1,
+                Span {
+                    enter: 300,
+                    leave: 400
+                }
+            ),
+            (
+                2,
+                Span {
+                    enter: 180,
+                    leave: 350
+                }
+            ),
+        ]
+    );
+    assert_eq!(instance.all_entries(700, 800).count(), 0);
+}
+
+#[test]
+fn test_merge_gap() {
+    let minute = 60;
+    let morning = Span {
+        enter: 9 * 60 * minute,
+        leave: 12 * 60 * minute,
+    };
+    let afternoon = Span {
+        enter: morning.leave + 3 * minute,
+        leave: 17 * 60 * minute,
+    };
+    for (gap, expect) in [
+        (
+            5,
+            Vec::from([Span {
+                enter: morning.enter,
+                leave: afternoon.leave,
+            }]),
+        ),
+        (1, Vec::from([morning, afternoon])),
+        (0, Vec::from([morning, afternoon])),
+    ] {
+        let mut instance = Instance::new(Language::En, Tz::UTC);
+        instance.merge_gap_minutes = gap;
+        instance.add_span(1, morning.enter, morning.leave).unwrap();
+        let (added, removed) = instance
+            .add_span(1, afternoon.enter, afternoon.leave)
+            .unwrap();
+        assert_eq!(removed, []);
+        assert_eq!(added, expect[expect.len() - 1]);
+        assert_eq!(instance.clearable(1, i64::MIN, i64::MAX), expect);
+    }
+
+    // joins the spans on both sides, whatever the order they come in
+    let mut instance = Instance::new(Language::En, Tz::UTC);
+    instance.merge_gap_minutes = 5;
+    instance.add_span(1, 0, 10 * minute).unwrap();
+    instance.add_span(1, 20 * minute, 30 * minute).unwrap();
+    instance.enter(1, 12 * minute);
+    let (added, _) = instance.leave(1, 17 * minute).unwrap();
+    assert_eq!(
+        added,
+        Span {
+            enter: 0,
+            leave: 30 * minute
+        }
+    );
+    assert_eq!(instance.clearable(1, i64::MIN, i64::MAX), [added]);
+}
@@ -0,0 +1,40 @@
+//! Layout of the state as it was saved before it carried a version, only read to migrate it
+//!
+//! Postcard is not self-describing, these structures must never change.
+
+use super::instance::Span;
+use crate::language::Language;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize)]
+pub struct AppState {
+    pub hook: Hook,
+    pub instances: HashMap<i64, Instance>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Hook {
+    pub port: u16,
+    pub domain: String,
+    pub bot_token: String,
+    pub secret_token: String,
+    pub cert_cert: String,
+    pub cert_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Instance {
+    pub language: Language,
+    pub time_zone: Tz,
+    pub persons: HashMap<i64, Person>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Person {
+    pub spans: Vec<Span>,
+    pub entered: Option<i64>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
@@ -1,16 +1,51 @@
-use pest::{Parser, error::Error, iterators::Pair};
+use pest::{
+    Parser,
+    error::{Error, ErrorVariant},
+    iterators::Pair,
+};
 use pest_derive::Parser;
-use std::{collections::HashMap, str::FromStr};
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq},
+};
+use std::{
+    fmt::{self, Display, Write},
+    str::FromStr,
+};
 
-#[derive(Parser, Debug, Clone, PartialEq, Eq)]
+#[derive(Parser, Debug, Clone)]
 #[grammar = "grammar.pest"]
 pub enum Json {
     Null,
     Bool(bool),
     Int(i64),
+    Float(f64),
     String(String),
     Array(Vec<Self>),
-    Object(HashMap<String, Self>),
+    /// Attributes in the order they were written
+    Object(Vec<(String, Self)>),
+}
+
+/// Objects compare equal regardless of the order of their attributes
+impl PartialEq for Json {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Null, Self::Null) => true,
+            (Self::Bool(lhs), Self::Bool(rhs)) => lhs == rhs,
+            (Self::Int(lhs), Self::Int(rhs)) => lhs == rhs,
+            (Self::Float(lhs), Self::Float(rhs)) => lhs == rhs,
+            (Self::String(lhs), Self::String(rhs)) => lhs == rhs,
+            (Self::Array(lhs), Self::Array(rhs)) => lhs == rhs,
+            (Self::Object(lhs), Self::Object(rhs)) => {
+                lhs.len() == rhs.len()
+                    && lhs
+                        .iter()
+                        .all(|(key, value)| rhs.iter().any(|(k, v)| k == key && v == value))
+            }
+            _ => false,
+        }
+    }
 }
 
 impl<'a> From<&'a str> for Json {
@@ -33,68 +68,247 @@ impl From<u32> for Json {
         Self::Int(value as i64)
     }
 }
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
 
+/// Duplicate object keys are accepted, the last value wins
 impl FromStr for Json {
     type Err = Error<Rule>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_str(s, false)
+    }
+}
+
+impl Json {
+    /// Like `from_str`, but duplicate object keys are an error
+    pub fn from_str_strict(s: &str) -> Result<Self, Error<Rule>> {
+        Self::parse_str(s, true)
+    }
+    fn parse_str(s: &str, strict: bool) -> Result<Self, Error<Rule>> {
         let node = Json::parse(Rule::value, s)?
             .next()
             .unwrap()
             .into_inner()
             .next()
             .unwrap();
-        Ok(node.into())
+        Self::from_node(node, strict)
     }
-}
-
-impl<'a> From<Pair<'a, Rule>> for Json {
-    fn from(node: Pair<'a, Rule>) -> Self {
-        match node.as_rule() {
+    fn from_node(node: Pair<'_, Rule>, strict: bool) -> Result<Self, Error<Rule>> {
+        Ok(match node.as_rule() {
             Rule::null => Self::Null,
             Rule::bool => Self::Bool(match node.as_str() {
                 "true" => true,
                 "false" => false,
                 _ => unreachable!(),
             }),
-            Rule::int => Self::Int(node.as_str().parse().unwrap()),
+            // integers beyond i64 keep their magnitude as a float, like deserialized ones
+            Rule::int => node
+                .as_str()
+                .parse()
+                .map_or_else(|_| Self::Float(node.as_str().parse().unwrap()), Self::Int),
+            Rule::float => Self::Float(node.as_str().parse().unwrap()),
             Rule::array => Self::Array(
                 node.into_inner()
-                    .map(|v| v.into_inner().next().unwrap().into())
-                    .collect(),
+                    .map(|v| Self::from_node(v.into_inner().next().unwrap(), strict))
+                    .collect::<Result<_, _>>()?,
             ),
-            Rule::string => Self::String(
-                node.into_inner()
-                    .map(|elem| match elem.as_rule() {
+            Rule::string => {
+                let mut string = String::new();
+                // consecutive \u escapes are UTF-16 code units, possibly surrogate pairs
+                let mut units = Vec::new();
+                for elem in node.into_inner() {
+                    if elem.as_rule() == Rule::char_uc {
+                        units.push(u16::from_str_radix(&elem.as_str()[1..], 16).unwrap());
+                        continue;
+                    }
+                    flush_utf16(&mut string, &mut units);
+                    string.push(match elem.as_rule() {
                         Rule::char => elem.as_str().chars().next().unwrap(),
                         Rule::char_bs => '\\',
+                        Rule::char_sl => '/',
                         Rule::char_sq => '\'',
                         Rule::char_dq => '\"',
                         Rule::char_ln => '\n',
+                        Rule::char_cr => '\r',
+                        Rule::char_tb => '\t',
+                        Rule::char_bk => '\u{8}',
+                        Rule::char_ff => '\u{c}',
                         c => unreachable!("found {:?}", c),
-                    })
-                    .collect(),
-            ),
-            Rule::object => Self::Object(
-                node.into_inner()
-                    .map(|attr| {
-                        let mut children = attr.into_inner();
-                        let key = children.next().unwrap();
-                        let value: Self =
-                            children.next().unwrap().into_inner().next().unwrap().into();
-                        let Self::String(key) = key.into() else {
-                            unreachable!()
-                        };
-                        (key, value)
-                    })
-                    .collect(),
-            ),
+                    });
+                }
+                flush_utf16(&mut string, &mut units);
+                Self::String(string)
+            }
+            Rule::object => {
+                let mut object: Vec<(String, Self)> = Vec::new();
+                for attr in node.into_inner() {
+                    let span = attr.as_span();
+                    let mut children = attr.into_inner();
+                    let Self::String(key) = Self::from_node(children.next().unwrap(), strict)?
+                    else {
+                        unreachable!()
+                    };
+                    let value = Self::from_node(
+                        children.next().unwrap().into_inner().next().unwrap(),
+                        strict,
+                    )?;
+                    match object.iter_mut().find(|(k, _)| *k == key) {
+                        Some(_) if strict => {
+                            return Err(Error::new_from_span(
+                                ErrorVariant::CustomError {
+                                    message: format!("duplicate key {:?}", key),
+                                },
+                                span,
+                            ));
+                        }
+                        // the key keeps its first position
+                        Some((_, previous)) => *previous = value,
+                        None => object.push((key, value)),
+                    }
+                }
+                Self::Object(object)
+            }
             rule => unreachable!("found {:?} for {:?}", rule, node.as_str()),
+        })
+    }
+}
+
+/// Decodes pending UTF-16 code units, unpaired surrogates become replacement characters
+fn flush_utf16(string: &mut String, units: &mut Vec<u16>) {
+    string.extend(
+        char::decode_utf16(units.drain(..)).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)),
+    );
+}
+
+/// Compact serialization, `{:#}` gives the pretty one
+impl Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let indent = f.alternate().then_some("    ");
+        self.write(f, indent, 0)
+    }
+}
+
+impl Json {
+    pub fn to_string_pretty(&self) -> String {
+        format!("{:#}", self)
+    }
+    fn write(&self, f: &mut impl Write, indent: Option<&str>, depth: usize) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Int(value) => write!(f, "{}", value),
+            // debug formatting keeps a fraction or an exponent, so it parses back as a float
+            Self::Float(value) if value.is_finite() => write!(f, "{:?}", value),
+            Self::Float(_) => write!(f, "null"),
+            Self::String(value) => write_str(f, value),
+            Self::Array(array) if array.is_empty() => write!(f, "[]"),
+            Self::Array(array) => {
+                write!(f, "[")?;
+                for (index, value) in array.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_newline(f, indent, depth + 1)?;
+                    value.write(f, indent, depth + 1)?;
+                }
+                write_newline(f, indent, depth)?;
+                write!(f, "]")
+            }
+            Self::Object(object) if object.is_empty() => write!(f, "{{}}"),
+            Self::Object(object) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in object.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_newline(f, indent, depth + 1)?;
+                    write_str(f, key)?;
+                    write!(f, ":")?;
+                    if indent.is_some() {
+                        write!(f, " ")?;
+                    }
+                    value.write(f, indent, depth + 1)?;
+                }
+                write_newline(f, indent, depth)?;
+                write!(f, "}}")
+            }
         }
     }
 }
 
+fn write_newline(f: &mut impl Write, indent: Option<&str>, depth: usize) -> fmt::Result {
+    if let Some(indent) = indent {
+        writeln!(f)?;
+        for _ in 0..depth {
+            write!(f, "{}", indent)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_str(f: &mut impl Write, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            '\u{8}' => write!(f, "\\b")?,
+            '\u{c}' => write!(f, "\\f")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
 impl Json {
+    /// Value of an object attribute
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        self.as_object()?
+            .iter()
+            .find_map(|(k, v)| (k == key).then_some(v))
+    }
+    /// Element of an array
+    pub fn index(&self, index: usize) -> Option<&Json> {
+        self.as_array()?.get(index)
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Self::Object(object) => Some(object),
+            _ => None,
+        }
+    }
     pub fn str(string: impl Into<String>) -> Json {
         Json::String(string.into())
     }
@@ -102,11 +316,100 @@ impl Json {
         Json::Array(Vec::from(array))
     }
     pub fn object<const N: usize>(object: [(&str, Json); N]) -> Json {
-        let mut map = HashMap::new();
-        for (key, value) in object {
-            map.insert(key.to_string(), value);
+        Json::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+}
+
+impl Serialize for Json {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Null => serializer.serialize_unit(),
+            Self::Bool(value) => serializer.serialize_bool(*value),
+            Self::Int(value) => serializer.serialize_i64(*value),
+            Self::Float(value) => serializer.serialize_f64(*value),
+            Self::String(value) => serializer.serialize_str(value),
+            Self::Array(array) => {
+                let mut seq = serializer.serialize_seq(Some(array.len()))?;
+                for value in array {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Self::Object(object) => {
+                let mut map = serializer.serialize_map(Some(object.len()))?;
+                for (key, value) in object {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Json {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(JsonVisitor)
+    }
+}
+
+struct JsonVisitor;
+
+impl<'de> Visitor<'de> for JsonVisitor {
+    type Value = Json;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a json value")
+    }
+    fn visit_unit<E: de::Error>(self) -> Result<Json, E> {
+        Ok(Json::Null)
+    }
+    fn visit_none<E: de::Error>(self) -> Result<Json, E> {
+        Ok(Json::Null)
+    }
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Json, D::Error> {
+        Json::deserialize(deserializer)
+    }
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Json, E> {
+        Ok(Json::Bool(value))
+    }
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Json, E> {
+        Ok(Json::Int(value))
+    }
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Json, E> {
+        // too large for an int, keep it approximately
+        Ok(i64::try_from(value).map_or(Json::Float(value as f64), Json::Int))
+    }
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Json, E> {
+        Ok(Json::Float(value))
+    }
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Json, E> {
+        Ok(Json::str(value))
+    }
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Json, E> {
+        Ok(Json::String(value))
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Json, A::Error> {
+        let mut array = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            array.push(value);
+        }
+        Ok(Json::Array(array))
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json, A::Error> {
+        let mut object: Vec<(String, Json)> = Vec::new();
+        while let Some((key, value)) = map.next_entry::<String, Json>()? {
+            // last value wins, as with `from_str`
+            match object.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, previous)) => *previous = value,
+                None => object.push((key, value)),
+            }
         }
-        Json::Object(map)
+        Ok(Json::Object(object))
     }
 }
 
@@ -167,3 +470,173 @@ fn test_parse() {
         assert_eq!(value, expect);
     }
 }
+
+#[test]
+#[allow(clippy::approx_constant)]
+fn test_parse_float() {
+    for (string, expect) in [
+        ("3.14", Json::Float(3.14)),
+        ("-0.5", Json::Float(-0.5)),
+        ("1e3", Json::Float(1000.0)),
+        ("2.5E-2", Json::Float(0.025)),
+        ("[1.5, 2]", Json::array([Json::Float(1.5), Json::Int(2)])),
+        ("12", Json::Int(12)),
+        ("-9223372036854775808", Json::Int(i64::MIN)),
+        ("9223372036854775808", Json::Float(9223372036854775808.0)),
+        ("-100000000000000000000", Json::Float(-1e20)),
+    ] {
+        let value: Json = match string.parse() {
+            Ok(value) => value,
+            Err(err) => panic!("{:?}", err),
+        };
+        assert_eq!(value, expect);
+    }
+}
+
+#[test]
+fn test_parse_escape() {
+    for (string, expect) in [
+        (r#""\u00e9""#, "é"),
+        (r#""\uD83D\uDE00""#, "😀"),
+        (r#""a\tb""#, "a\tb"),
+        (r#""\r\b\f\/""#, "\r\u{8}\u{c}/"),
+        (r#""\uD83Dx""#, "\u{fffd}x"),
+    ] {
+        let value: Json = match string.parse() {
+            Ok(value) => value,
+            Err(err) => panic!("{:?}", err),
+        };
+        assert_eq!(value, Json::str(expect));
+    }
+}
+
+#[test]
+fn test_serialize() {
+    assert_eq!(
+        Json::array([
+            Json::Null,
+            Json::Bool(false),
+            Json::Int(-3),
+            Json::Float(1000.0),
+            Json::str("a\"b\\c\n\u{1}"),
+            Json::object([("key", Json::array([]))]),
+        ])
+        .to_string(),
+        r#"[null,false,-3,1000.0,"a\"b\\c\n\u0001",{"key":[]}]"#
+    );
+    assert_eq!(
+        Json::object([("spans", Json::array([Json::Int(1), Json::Int(2)]))]).to_string_pretty(),
+        indoc::indoc! {r#"
+            {
+                "spans": [
+                    1,
+                    2
+                ]
+            }"#}
+    );
+}
+
+#[test]
+fn test_round_trip() {
+    let value = Json::object([
+        ("name", Json::str("Eddie \"the\" \u{e9}\t😀")),
+        ("hours", Json::Float(2.5e-2)),
+        ("big", Json::Float(1e21)),
+        ("minutes", Json::Int(150)),
+        (
+            "spans",
+            Json::array([
+                Json::object([("enter", Json::Int(1)), ("leave", Json::Null)]),
+                Json::array([Json::Bool(true)]),
+            ]),
+        ),
+        ("empty", Json::object([])),
+    ]);
+    for string in [value.to_string(), value.to_string_pretty()] {
+        let parsed: Json = match string.parse() {
+            Ok(parsed) => parsed,
+            Err(err) => panic!("{:?}", err),
+        };
+        assert_eq!(parsed, value);
+    }
+}
+
+#[test]
+fn test_object_order() {
+    let json: Json = r#"{"b":1,"a":2}"#.parse().unwrap();
+    assert_eq!(json.to_string(), r#"{"b":1,"a":2}"#);
+    assert_eq!(
+        json,
+        Json::object([("a", Json::Int(2)), ("b", Json::Int(1))])
+    );
+    assert_ne!(
+        json,
+        Json::object([("a", Json::Int(1)), ("b", Json::Int(2))])
+    );
+}
+
+#[test]
+fn test_duplicate_key() {
+    let string = r#"{"a":1,"b":3,"a":2}"#;
+    let json: Json = string.parse().unwrap();
+    assert_eq!(json.to_string(), r#"{"a":2,"b":3}"#);
+    assert!(Json::from_str_strict(string).is_err());
+    assert!(Json::from_str_strict(r#"{"a":{"a":1},"b":[{"a":1,"a":2}]}"#).is_err());
+    assert_eq!(
+        Json::from_str_strict(r#"{"a":1,"b":{"a":2}}"#).unwrap(),
+        Json::object([
+            ("a", Json::Int(1)),
+            ("b", Json::object([("a", Json::Int(2))]))
+        ])
+    );
+}
+
+#[test]
+fn test_accessors() {
+    let json: Json = r#"{"name":"Eddie","admin":false,"spans":[{"enter":10,"leave":null}]}"#
+        .parse()
+        .unwrap();
+    assert_eq!(json.get("name").and_then(Json::as_str), Some("Eddie"));
+    assert_eq!(json.get("admin").and_then(Json::as_bool), Some(false));
+    assert_eq!(json.get("missing"), None);
+    assert_eq!(json.get("name").and_then(Json::as_i64), None);
+    let span = json
+        .get("spans")
+        .and_then(|v| v.index(0))
+        .and_then(Json::as_object)
+        .unwrap();
+    assert_eq!(span.len(), 2);
+    assert_eq!(
+        json.get("spans")
+            .and_then(|v| v.index(0))
+            .and_then(|v| v.get("enter"))
+            .and_then(Json::as_i64),
+        Some(10)
+    );
+    assert_eq!(json.get("spans").and_then(|v| v.index(1)), None);
+    assert_eq!(
+        json.get("spans").and_then(Json::as_array).map(<[_]>::len),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_serde() {
+    let value = Json::object([
+        ("name", Json::str("Eddie")),
+        ("admin", Json::Bool(true)),
+        ("hours", Json::Float(7.5)),
+        ("spans", Json::array([Json::Int(1), Json::Null])),
+        ("zone", Json::object([("offset", Json::Int(-3600))])),
+    ]);
+    let string = serde_json::to_string(&value).unwrap();
+    assert_eq!(string, value.to_string());
+    assert_eq!(serde_json::from_str::<Json>(&string).unwrap(), value);
+    assert_eq!(
+        serde_json::from_value::<Json>(serde_json::json!({"b": [1, 2.5], "a": null})).unwrap(),
+        Json::object([
+            ("a", Json::Null),
+            ("b", Json::array([Json::Int(1), Json::Float(2.5)])),
+        ])
+    );
+}
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use typst::{
     Library,
@@ -12,8 +13,10 @@ use typst::{
     utils::LazyHash,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DocFormat {
+    #[default]
     Png,
     Pdf,
 }
@@ -152,6 +155,34 @@ impl Renderer {
         bytes: HashMap<&str, Vec<u8>>,
         format: DocFormat,
     ) -> Result<Vec<u8>, ()> {
+        let document = self.compile(main, sources, bytes)?;
+        match format {
+            DocFormat::Png => typst_render::render_merged(&document, 2.0, Abs::mm(2.0), None)
+                .encode_png()
+                .map_err(|_| ()),
+            DocFormat::Pdf => typst_pdf::pdf(&document, &Default::default()).map_err(|_| ()),
+        }
+    }
+    /// Renders every page to its own png image
+    pub fn render_pages(
+        &self,
+        main: &str,
+        sources: HashMap<&str, String>,
+        bytes: HashMap<&str, Vec<u8>>,
+    ) -> Result<Vec<Vec<u8>>, ()> {
+        let document = self.compile(main, sources, bytes)?;
+        document
+            .pages
+            .iter()
+            .map(|page| typst_render::render(page, 2.0).encode_png().map_err(|_| ()))
+            .collect()
+    }
+    fn compile(
+        &self,
+        main: &str,
+        sources: HashMap<&str, String>,
+        bytes: HashMap<&str, Vec<u8>>,
+    ) -> Result<PagedDocument, ()> {
         let main_id = FileId::new_fake(VirtualPath::new("main.typ"));
         let result = typst::compile::<PagedDocument>(&RendererWithFiles {
             main_id,
@@ -169,13 +200,7 @@ impl Renderer {
                 .map(|(path, bytes)| (FileId::new(None, VirtualPath::new(path)), Bytes::new(bytes)))
                 .collect(),
         });
-        let document = result.output.map_err(|_| ())?;
-        match format {
-            DocFormat::Png => typst_render::render_merged(&document, 2.0, Abs::mm(2.0), None)
-                .encode_png()
-                .map_err(|_| ()),
-            DocFormat::Pdf => typst_pdf::pdf(&document, &Default::default()).map_err(|_| ()),
-        }
+        result.output.map_err(|_| ())
     }
 }
 
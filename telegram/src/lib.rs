@@ -2,15 +2,87 @@ use reqwest::{
     Client, Error, RequestBuilder, Response,
     multipart::{Form, Part},
 };
-use serde::{Deserialize, Serialize};
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{DeserializeOwned, IgnoredAny},
+};
 use std::borrow::Cow;
 
+#[derive(Debug)]
+pub enum TelegramError {
+    Request(Error),
+    Deserialize(serde_json::Error),
+    Api {
+        error_code: Option<i32>,
+        description: Option<String>,
+    },
+}
+
+impl From<Error> for TelegramError {
+    fn from(error: Error) -> Self {
+        Self::Request(error)
+    }
+}
+impl From<serde_json::Error> for TelegramError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Deserialize(error)
+    }
+}
+
+/// The envelope wrapping every Bot API response
+#[derive(Debug, Clone, Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    #[serde(default)]
+    result: Option<T>,
+    #[serde(default)]
+    error_code: Option<i32>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct SentMessage {
+    message_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BotCommand<'a> {
+    command: &'a str,
+    description: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct InputMedia {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    media: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Update {
     pub update_id: u64,
     #[serde(default)]
     pub message: Option<Message>,
     pub my_chat_member: Option<ChatMemberUpdated>,
+    #[serde(default)]
+    pub callback_query: Option<CallbackQuery>,
+    #[serde(default)]
+    pub edited_message: Option<Message>,
+    #[serde(default)]
+    pub channel_post: Option<Unmodeled>,
+    #[serde(default)]
+    pub edited_channel_post: Option<Unmodeled>,
+}
+
+/// Content of an update that is not modeled, only its presence is known
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Hash)]
+pub struct Unmodeled;
+
+impl<'de> Deserialize<'de> for Unmodeled {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        IgnoredAny::deserialize(deserializer).map(|_| Self)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -27,6 +99,10 @@ pub struct Message {
     pub group_chat_created: bool,
     #[serde(default)]
     pub left_chat_member: Option<User>,
+    #[serde(default)]
+    pub message_thread_id: Option<i32>,
+    #[serde(default)]
+    pub is_topic_message: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
@@ -50,6 +126,8 @@ pub struct User {
     #[serde(default)]
     pub last_name: Option<String>,
     #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
     pub language_code: Option<String>,
 }
 
@@ -88,6 +166,47 @@ pub enum ChatMember {
     Banned { user: User },
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: User,
+    #[serde(default)]
+    pub message: Option<Message>,
+    #[serde(default)]
+    pub data: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl InlineKeyboardButton {
+    pub fn callback(text: impl Into<String>, data: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            callback_data: Some(data.into()),
+            url: None,
+        }
+    }
+    pub fn url(text: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            callback_data: None,
+            url: Some(url.into()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum ChatType {
     #[serde(rename = "private")]
@@ -100,68 +219,410 @@ pub enum ChatType {
     Channel,
 }
 
-pub async fn send_photo(token: &str, photo: Vec<u8>, chat_id: i64) -> Result<Response, Error> {
-    client(token, "sendPhoto")
-        .multipart(
-            Form::new()
-                .part("chat_id", Part::text(format!("{}", chat_id)))
-                .part("photo", Part::bytes(photo).file_name("month.png")),
-        )
-        .send()
-        .await
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChatAction {
+    Typing,
+    UploadPhoto,
+    RecordVideo,
+    UploadVideo,
+    RecordVoice,
+    UploadVoice,
+    UploadDocument,
+    ChooseSticker,
+    FindLocation,
+    RecordVideoNote,
+    UploadVideoNote,
 }
 
-pub async fn send_document(
-    token: &str,
-    document: Vec<u8>,
-    chat_id: i64,
-) -> Result<Response, Error> {
-    client(token, "sendDocument")
-        .multipart(
-            Form::new()
-                .part("chat_id", Part::text(format!("{}", chat_id)))
-                .part("document", Part::bytes(document).file_name("month.pdf")),
-        )
-        .send()
-        .await
+impl ChatAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Typing => "typing",
+            Self::UploadPhoto => "upload_photo",
+            Self::RecordVideo => "record_video",
+            Self::UploadVideo => "upload_video",
+            Self::RecordVoice => "record_voice",
+            Self::UploadVoice => "upload_voice",
+            Self::UploadDocument => "upload_document",
+            Self::ChooseSticker => "choose_sticker",
+            Self::FindLocation => "find_location",
+            Self::RecordVideoNote => "record_video_note",
+            Self::UploadVideoNote => "upload_video_note",
+        }
+    }
 }
 
-pub async fn send_text(token: &str, text: String, chat_id: i64) -> Result<Response, Error> {
-    client(token, "sendMessage")
-        .multipart(
-            Form::new()
-                .part("chat_id", Part::text(format!("{}", chat_id)))
-                .part("text", Part::text(text)),
-        )
-        .send()
-        .await
+/// Client to the Bot API, reusing its connections across requests
+#[derive(Debug, Clone)]
+pub struct Bot {
+    client: Client,
+    token: String,
+    api: String,
 }
 
-pub async fn send_markdown(token: &str, text: String, chat_id: i64) -> Result<Response, Error> {
-    client(token, "sendMessage")
-        .multipart(
-            Form::new()
-                .part("chat_id", Part::text(format!("{}", chat_id)))
-                .part("text", Part::text(text))
-                .part("parse_mode", Part::text("MarkdownV2")),
-        )
-        .send()
-        .await
-}
+/// Maximum number of items Telegram accepts in a single media group
+pub const MEDIA_GROUP_LIMIT: usize = 10;
 
-pub fn set_webhook(token: &str, url: String) -> SetWebhook<'_> {
-    SetWebhook {
-        token,
-        url,
-        drop_pending_updates: false,
-        allowed_updates: Vec::new(),
-        certificate: None,
-        secret_token: None,
+impl Bot {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            token: token.into(),
+            api: "https://api.telegram.org".to_string(),
+        }
+    }
+    /// Targets another Bot API server, like a self-hosted one
+    pub fn with_api(self, api: impl Into<String>) -> Self {
+        Self {
+            api: api.into(),
+            ..self
+        }
+    }
+    fn request(&self, method: &str) -> RequestBuilder {
+        self.client
+            .post(format!("{}/bot{}/{}", self.api, self.token, method))
+    }
+    /// Identity of the bot, useful to check that the credentials are valid
+    pub async fn get_me(&self) -> Result<User, TelegramError> {
+        let response = self.request("getMe").send().await?;
+        parse_result(response).await
+    }
+    pub async fn send_chat_action(
+        &self,
+        chat_id: i64,
+        action: ChatAction,
+        message_thread_id: Option<i32>,
+    ) -> Result<(), TelegramError> {
+        let response = self
+            .request("sendChatAction")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part_opt("message_thread_id", message_thread_id.map(thread_part))
+                    .part("action", Part::text(action.as_str())),
+            )
+            .send()
+            .await?;
+        parse_result::<bool>(response).await?;
+        Ok(())
+    }
+    pub async fn send_photo(
+        &self,
+        photo: Vec<u8>,
+        chat_id: i64,
+        message_thread_id: Option<i32>,
+    ) -> Result<i32, TelegramError> {
+        let response = self
+            .request("sendPhoto")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part_opt("message_thread_id", message_thread_id.map(thread_part))
+                    .part("photo", Part::bytes(photo).file_name("month.png")),
+            )
+            .send()
+            .await?;
+        Ok(parse_result::<SentMessage>(response).await?.message_id)
+    }
+    pub async fn send_document(
+        &self,
+        document: Vec<u8>,
+        chat_id: i64,
+        message_thread_id: Option<i32>,
+    ) -> Result<i32, TelegramError> {
+        let response = self
+            .request("sendDocument")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part_opt("message_thread_id", message_thread_id.map(thread_part))
+                    .part("document", Part::bytes(document).file_name("month.pdf")),
+            )
+            .send()
+            .await?;
+        Ok(parse_result::<SentMessage>(response).await?.message_id)
+    }
+    /// Sends photos as albums, in chunks of at most `MEDIA_GROUP_LIMIT`
+    ///
+    /// Telegram refuses albums of a single item, such a chunk is sent as a regular photo.
+    pub async fn send_media_group(
+        &self,
+        chat_id: i64,
+        photos: Vec<Vec<u8>>,
+        message_thread_id: Option<i32>,
+    ) -> Result<Vec<i32>, TelegramError> {
+        let mut message_ids = Vec::new();
+        let mut photos = photos.into_iter().peekable();
+        while photos.peek().is_some() {
+            let mut chunk: Vec<Vec<u8>> = photos.by_ref().take(MEDIA_GROUP_LIMIT).collect();
+            if chunk.len() == 1 {
+                message_ids.push(
+                    self.send_photo(chunk.pop().unwrap(), chat_id, message_thread_id)
+                        .await?,
+                );
+                continue;
+            }
+            let media: Vec<InputMedia> = (0..chunk.len())
+                .map(|index| InputMedia {
+                    kind: "photo",
+                    media: format!("attach://file{index}"),
+                })
+                .collect();
+            let mut form = Form::new()
+                .part("chat_id", Part::text(format!("{}", chat_id)))
+                .part_opt("message_thread_id", message_thread_id.map(thread_part))
+                .part("media", Part::text(serde_json::to_string(&media).unwrap()));
+            for (index, photo) in chunk.into_iter().enumerate() {
+                form = form.part(
+                    format!("file{index}"),
+                    Part::bytes(photo).file_name(format!("file{index}.png")),
+                );
+            }
+            let response = self
+                .request("sendMediaGroup")
+                .multipart(form)
+                .send()
+                .await?;
+            message_ids.extend(
+                parse_result::<Vec<SentMessage>>(response)
+                    .await?
+                    .into_iter()
+                    .map(|message| message.message_id),
+            );
+        }
+        Ok(message_ids)
+    }
+    pub async fn send_text(
+        &self,
+        text: String,
+        chat_id: i64,
+        message_thread_id: Option<i32>,
+    ) -> Result<i32, TelegramError> {
+        let response = self
+            .request("sendMessage")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part_opt("message_thread_id", message_thread_id.map(thread_part))
+                    .part("text", Part::text(text)),
+            )
+            .send()
+            .await?;
+        Ok(parse_result::<SentMessage>(response).await?.message_id)
+    }
+    pub async fn send_markdown(
+        &self,
+        text: String,
+        chat_id: i64,
+        message_thread_id: Option<i32>,
+    ) -> Result<i32, TelegramError> {
+        let response = self
+            .request("sendMessage")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part_opt("message_thread_id", message_thread_id.map(thread_part))
+                    .part("text", Part::text(text))
+                    .part("parse_mode", Part::text("MarkdownV2")),
+            )
+            .send()
+            .await?;
+        Ok(parse_result::<SentMessage>(response).await?.message_id)
+    }
+    pub async fn send_text_with_keyboard(
+        &self,
+        text: String,
+        chat_id: i64,
+        markup: InlineKeyboardMarkup,
+        message_thread_id: Option<i32>,
+    ) -> Result<i32, TelegramError> {
+        let response = self
+            .request("sendMessage")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part_opt("message_thread_id", message_thread_id.map(thread_part))
+                    .part("text", Part::text(text))
+                    .part(
+                        "reply_markup",
+                        Part::text(serde_json::to_string(&markup).unwrap())
+                            .mime_str("application/json")
+                            .unwrap(),
+                    ),
+            )
+            .send()
+            .await?;
+        Ok(parse_result::<SentMessage>(response).await?.message_id)
+    }
+    pub async fn send_markdown_with_keyboard(
+        &self,
+        text: String,
+        chat_id: i64,
+        markup: InlineKeyboardMarkup,
+        message_thread_id: Option<i32>,
+    ) -> Result<i32, TelegramError> {
+        let response = self
+            .request("sendMessage")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part_opt("message_thread_id", message_thread_id.map(thread_part))
+                    .part("text", Part::text(text))
+                    .part("parse_mode", Part::text("MarkdownV2"))
+                    .part(
+                        "reply_markup",
+                        Part::text(serde_json::to_string(&markup).unwrap())
+                            .mime_str("application/json")
+                            .unwrap(),
+                    ),
+            )
+            .send()
+            .await?;
+        Ok(parse_result::<SentMessage>(response).await?.message_id)
+    }
+    pub async fn answer_callback_query(
+        &self,
+        callback_query_id: String,
+        text: Option<String>,
+    ) -> Result<(), TelegramError> {
+        let response = self
+            .request("answerCallbackQuery")
+            .multipart(
+                Form::new()
+                    .part("callback_query_id", Part::text(callback_query_id))
+                    .part_opt("text", text.map(Part::text)),
+            )
+            .send()
+            .await?;
+        parse_result::<bool>(response).await?;
+        Ok(())
+    }
+    pub async fn edit_message_text(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    ) -> Result<Message, TelegramError> {
+        let response = self
+            .request("editMessageText")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part("message_id", Part::text(format!("{}", message_id)))
+                    .part("text", Part::text(text)),
+            )
+            .send()
+            .await?;
+        parse_result(response).await
+    }
+    pub async fn edit_message_markdown(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    ) -> Result<Message, TelegramError> {
+        let response = self
+            .request("editMessageText")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part("message_id", Part::text(format!("{}", message_id)))
+                    .part("text", Part::text(text))
+                    .part("parse_mode", Part::text("MarkdownV2")),
+            )
+            .send()
+            .await?;
+        parse_result(response).await
+    }
+    pub async fn delete_message(&self, chat_id: i64, message_id: i32) -> Result<(), TelegramError> {
+        let response = self
+            .request("deleteMessage")
+            .multipart(
+                Form::new()
+                    .part("chat_id", Part::text(format!("{}", chat_id)))
+                    .part("message_id", Part::text(format!("{}", message_id))),
+            )
+            .send()
+            .await?;
+        parse_result::<bool>(response).await?;
+        Ok(())
+    }
+    /// Sets the command menu, `commands` are pairs of command and description
+    pub async fn set_my_commands(
+        &self,
+        commands: &[(String, String)],
+        language_code: Option<&str>,
+    ) -> Result<(), TelegramError> {
+        let commands: Vec<BotCommand> = commands
+            .iter()
+            .map(|(command, description)| BotCommand {
+                command,
+                description,
+            })
+            .collect();
+        let response = self
+            .request("setMyCommands")
+            .multipart(
+                Form::new()
+                    .part(
+                        "commands",
+                        Part::text(serde_json::to_string(&commands).unwrap()),
+                    )
+                    .part_opt(
+                        "language_code",
+                        language_code.map(|code| Part::text(code.to_string())),
+                    ),
+            )
+            .send()
+            .await?;
+        parse_result::<bool>(response).await?;
+        Ok(())
+    }
+    /// Long polls for updates, waiting up to `timeout` seconds
+    ///
+    /// Updates are acknowledged by passing the offset given by `ack_offset` to the next call,
+    /// they will not be delivered again.
+    pub async fn get_updates(
+        &self,
+        offset: Option<i64>,
+        timeout: u32,
+        allowed_updates: &[&str],
+    ) -> Result<Vec<Update>, TelegramError> {
+        let response = self
+            .request("getUpdates")
+            .multipart(
+                Form::new()
+                    .part_opt(
+                        "offset",
+                        offset.map(|offset| Part::text(format!("{}", offset))),
+                    )
+                    .part("timeout", Part::text(format!("{}", timeout)))
+                    .part(
+                        "allowed_updates",
+                        Part::text(serde_json::to_string(allowed_updates).unwrap()),
+                    ),
+            )
+            .send()
+            .await?;
+        parse_result(response).await
+    }
+    pub async fn delete_webhook(&self) -> Result<Response, Error> {
+        self.request("deleteWebhook").send().await
+    }
+    pub fn set_webhook(&self, url: String) -> SetWebhook<'_> {
+        SetWebhook {
+            bot: self,
+            url,
+            drop_pending_updates: false,
+            allowed_updates: Vec::new(),
+            certificate: None,
+            secret_token: None,
+        }
     }
 }
 
 pub struct SetWebhook<'a> {
-    token: &'a str,
+    bot: &'a Bot,
     url: String,
     allowed_updates: Vec<String>,
     drop_pending_updates: bool,
@@ -192,7 +653,8 @@ impl<'a> SetWebhook<'a> {
         }
     }
     pub async fn send(self) -> Result<Response, Error> {
-        client(self.token, "setWebhook")
+        self.bot
+            .request("setWebhook")
             .multipart(
                 Form::new()
                     .part("url", Part::text(self.url))
@@ -219,12 +681,35 @@ impl<'a> SetWebhook<'a> {
     }
 }
 
-pub async fn delete_webhook(token: &str) -> Result<Response, Error> {
-    client(token, "deleteWebhook").send().await
+/// Offset acknowledging every given update, `None` if there is none
+pub fn ack_offset(updates: &[Update]) -> Option<i64> {
+    updates
+        .iter()
+        .map(|update| update.update_id as i64 + 1)
+        .max()
 }
 
-fn client(token: &str, method: &str) -> RequestBuilder {
-    Client::new().post(format!("https://api.telegram.org/bot{}/{}", token, method))
+fn thread_part(message_thread_id: i32) -> Part {
+    Part::text(format!("{}", message_thread_id))
+}
+
+async fn parse_result<T: DeserializeOwned>(response: Response) -> Result<T, TelegramError> {
+    let bytes = response.bytes().await?;
+    match serde_json::from_slice(&bytes)? {
+        ApiResponse {
+            ok: true,
+            result: Some(result),
+            ..
+        } => Ok(result),
+        ApiResponse {
+            error_code,
+            description,
+            ..
+        } => Err(TelegramError::Api {
+            error_code,
+            description,
+        }),
+    }
 }
 
 trait FormExt {
@@ -243,3 +728,406 @@ impl FormExt for Form {
         }
     }
 }
+
+#[test]
+fn test_inline_keyboard_serialization() {
+    let markup = InlineKeyboardMarkup {
+        inline_keyboard: vec![
+            vec![
+                InlineKeyboardButton::callback("July", "month 7"),
+                InlineKeyboardButton::callback("August", "month 8"),
+            ],
+            vec![InlineKeyboardButton::callback("PDF", "month pdf")],
+        ],
+    };
+    assert_eq!(
+        serde_json::to_string(&markup).unwrap(),
+        concat!(
+            r#"{"inline_keyboard":["#,
+            r#"[{"text":"July","callback_data":"month 7"},{"text":"August","callback_data":"month 8"}],"#,
+            r#"[{"text":"PDF","callback_data":"month pdf"}]"#,
+            r#"]}"#,
+        )
+    );
+}
+
+#[test]
+fn test_callback_query_deserialization() {
+    let update: Update = serde_json::from_str(
+        r#"{
+            "update_id": 10000,
+            "callback_query": {
+                "id": "4382bfdwdsb323b2d9",
+                "from": {
+                    "id": 1111111,
+                    "is_bot": false,
+                    "first_name": "Eddie",
+                    "language_code": "es"
+                },
+                "message": {
+                    "message_id": 1365,
+                    "from": {
+                        "id": 2222222,
+                        "is_bot": true,
+                        "first_name": "Fichar"
+                    },
+                    "chat": {
+                        "id": -1001234,
+                        "title": "Atelier",
+                        "type": "group"
+                    },
+                    "date": 1756400000,
+                    "text": "Pick a month"
+                },
+                "chat_instance": "-4821341515134",
+                "data": "month 7"
+            }
+        }"#,
+    )
+    .unwrap();
+    let callback_query = update.callback_query.unwrap();
+    assert_eq!(callback_query.id, "4382bfdwdsb323b2d9");
+    assert_eq!(callback_query.from.id, 1111111);
+    assert_eq!(callback_query.data.as_deref(), Some("month 7"));
+    let message = callback_query.message.unwrap();
+    assert_eq!(message.message_id, 1365);
+    assert_eq!(message.chat.id, -1001234);
+    assert_eq!(message.chat.kind, ChatType::Group);
+}
+
+#[test]
+fn test_edited_message_deserialization() {
+    let update: Update = serde_json::from_str(
+        r#"{
+            "update_id": 10001,
+            "edited_message": {
+                "message_id": 1366,
+                "from": {
+                    "id": 1111111,
+                    "is_bot": false,
+                    "first_name": "Eddie"
+                },
+                "chat": {
+                    "id": -1001234,
+                    "title": "Atelier",
+                    "type": "group"
+                },
+                "date": 1756400000,
+                "edit_date": 1756400060,
+                "text": "enter 18h30"
+            }
+        }"#,
+    )
+    .unwrap();
+    assert!(update.message.is_none());
+    let message = update.edited_message.unwrap();
+    assert_eq!(message.message_id, 1366);
+    assert_eq!(message.date, 1756400000);
+    assert_eq!(message.text.as_deref(), Some("enter 18h30"));
+}
+
+/// Serves the given responses in order on a local port, keeping connections alive
+///
+/// Returns the address of the server, and a handle yielding the raw requests received along
+/// with the number of connections that were opened.
+#[cfg(test)]
+fn mock_server(
+    responses: &'static [&'static str],
+) -> (String, std::thread::JoinHandle<(Vec<String>, usize)>) {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let api = format!("http://{}", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        let mut connections = 0;
+        while requests.len() < responses.len() {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_millis(500)))
+                .unwrap();
+            connections += 1;
+            'connection: while requests.len() < responses.len() {
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                loop {
+                    let read = match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break 'connection,
+                        Ok(read) => read,
+                    };
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).to_lowercase();
+                    let complete = match text.split_once("\r\n\r\n") {
+                        Some((headers, body)) => match headers
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length: "))
+                        {
+                            Some(length) => body.len() >= length.trim().parse().unwrap(),
+                            None => !headers.contains("chunked") || body.ends_with("0\r\n\r\n"),
+                        },
+                        None => false,
+                    };
+                    if complete {
+                        break;
+                    }
+                }
+                let response = responses[requests.len()];
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+                requests.push(String::from_utf8_lossy(&request).into_owned());
+            }
+        }
+        (requests, connections)
+    });
+    (api, server)
+}
+
+#[tokio::test]
+async fn test_edit_message_text() {
+    let (api, server) = mock_server(&[
+        r#"{"ok":true,"result":{"message_id":56,"from":{"id":2222222,"first_name":"Fichar"},"chat":{"id":1234,"type":"private"},"date":1756400000,"text":"done"}}"#,
+        r#"{"ok":false,"error_code":400,"description":"Bad Request: message is not modified"}"#,
+    ]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    let message = bot
+        .edit_message_text(1234, 56, "done".into())
+        .await
+        .unwrap();
+    assert_eq!(message.message_id, 56);
+    assert_eq!(message.text.as_deref(), Some("done"));
+    assert!(matches!(
+        bot.edit_message_markdown(1234, 56, "done".into()).await,
+        Err(TelegramError::Api {
+            error_code: Some(400),
+            ..
+        })
+    ));
+    let (requests, _) = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /botTOKEN/editMessageText "));
+    assert!(requests[0].contains("name=\"chat_id\"\r\n\r\n1234\r\n"));
+    assert!(requests[0].contains("name=\"message_id\"\r\n\r\n56\r\n"));
+    assert!(requests[0].contains("name=\"text\"\r\n\r\ndone\r\n"));
+}
+
+#[tokio::test]
+async fn test_answer_callback_query() {
+    let (api, server) = mock_server(&[
+        r#"{"ok":true,"result":true}"#,
+        r#"{"ok":false,"error_code":400,"description":"Bad Request: query is too old"}"#,
+    ]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    bot.answer_callback_query("789".into(), Some("saved".into()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        bot.answer_callback_query("789".into(), None).await,
+        Err(TelegramError::Api {
+            error_code: Some(400),
+            ..
+        })
+    ));
+    let (requests, _) = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /botTOKEN/answerCallbackQuery "));
+    assert!(requests[0].contains("name=\"callback_query_id\"\r\n\r\n789\r\n"));
+    assert!(requests[0].contains("name=\"text\"\r\n\r\nsaved\r\n"));
+    assert!(!requests[1].contains("name=\"text\""));
+}
+
+#[tokio::test]
+async fn test_delete_message() {
+    let (api, server) = mock_server(&[r#"{"ok":true,"result":true}"#]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    bot.delete_message(-1234, 56).await.unwrap();
+    let (requests, _) = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /botTOKEN/deleteMessage "));
+    assert!(requests[0].contains("name=\"chat_id\"\r\n\r\n-1234\r\n"));
+    assert!(requests[0].contains("name=\"message_id\"\r\n\r\n56\r\n"));
+}
+
+#[tokio::test]
+async fn test_send_text_message_id() {
+    let (api, server) = mock_server(&[
+        r#"{"ok":true,"result":{"message_id":78,"chat":{"id":1234,"type":"private"},"date":1756400000,"text":"ok"}}"#,
+        r#"{"ok":false,"error_code":400,"description":"Bad Request: chat not found"}"#,
+    ]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    assert_eq!(bot.send_text("ok".into(), 1234, None).await.unwrap(), 78);
+    match bot.send_text("ok".into(), 1234, None).await {
+        Err(TelegramError::Api {
+            error_code: Some(400),
+            description: Some(description),
+        }) => assert_eq!(description, "Bad Request: chat not found"),
+        other => panic!("unexpected {other:?}"),
+    }
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn test_send_media_group() {
+    let (api, server) = mock_server(&[
+        r#"{"ok":true,"result":[{"message_id":1},{"message_id":2},{"message_id":3}]}"#,
+    ]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    let message_ids = bot
+        .send_media_group(1234, vec![vec![0; 8], vec![1; 8], vec![2; 8]], None)
+        .await
+        .unwrap();
+    assert_eq!(message_ids, [1, 2, 3]);
+    let (requests, _) = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /botTOKEN/sendMediaGroup "));
+    assert!(requests[0].contains(concat!(
+        "name=\"media\"\r\n\r\n",
+        r#"[{"type":"photo","media":"attach://file0"},"#,
+        r#"{"type":"photo","media":"attach://file1"},"#,
+        r#"{"type":"photo","media":"attach://file2"}]"#,
+    )));
+    for index in 0..3 {
+        assert!(requests[0].contains(&format!(
+            "name=\"file{index}\"; filename=\"file{index}.png\""
+        )));
+    }
+    assert!(!requests[0].contains("name=\"file3\""));
+}
+
+#[tokio::test]
+async fn test_set_my_commands() {
+    let commands = [
+        ("enter".to_string(), "Register entering".to_string()),
+        ("month".to_string(), "Summary of the month".to_string()),
+    ];
+
+    let (api, server) = mock_server(&[
+        r#"{"ok":true,"result":true}"#,
+        r#"{"ok":true,"result":true}"#,
+    ]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    bot.set_my_commands(&commands, Some("es")).await.unwrap();
+    bot.set_my_commands(&commands, None).await.unwrap();
+    let (requests, _) = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /botTOKEN/setMyCommands "));
+    assert!(requests[0].contains(concat!(
+        "name=\"commands\"\r\n\r\n",
+        r#"[{"command":"enter","description":"Register entering"},"#,
+        r#"{"command":"month","description":"Summary of the month"}]"#,
+    )));
+    assert!(requests[0].contains("name=\"language_code\"\r\n\r\nes\r\n"));
+    assert!(!requests[1].contains("name=\"language_code\""));
+}
+
+#[tokio::test]
+async fn test_send_chat_action() {
+    let (api, server) = mock_server(&[r#"{"ok":true,"result":true}"#]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    bot.send_chat_action(1234, ChatAction::UploadPhoto, None)
+        .await
+        .unwrap();
+    let (requests, _) = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /botTOKEN/sendChatAction "));
+    assert!(requests[0].contains("name=\"action\"\r\n\r\nupload_photo\r\n"));
+    assert_eq!(ChatAction::UploadDocument.as_str(), "upload_document");
+    assert_eq!(ChatAction::RecordVideoNote.as_str(), "record_video_note");
+}
+
+#[tokio::test]
+async fn test_get_me() {
+    let (api, server) = mock_server(&[
+        r#"{
+            "ok": true,
+            "result": {
+                "id": 2222222,
+                "is_bot": true,
+                "first_name": "Fichar",
+                "username": "fichar_bot",
+                "can_join_groups": true,
+                "can_read_all_group_messages": false,
+                "supports_inline_queries": false
+            }
+        }"#,
+        r#"{"ok":false,"error_code":401,"description":"Unauthorized"}"#,
+    ]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    let user = bot.get_me().await.unwrap();
+    assert_eq!(user.id, 2222222);
+    assert_eq!(user.first_name.as_deref(), Some("Fichar"));
+    assert_eq!(user.username.as_deref(), Some("fichar_bot"));
+    match bot.get_me().await {
+        Err(TelegramError::Api {
+            error_code: Some(401),
+            description: Some(description),
+        }) => assert_eq!(description, "Unauthorized"),
+        other => panic!("unexpected {other:?}"),
+    }
+    let (requests, _) = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /botTOKEN/getMe "));
+}
+
+#[tokio::test]
+async fn test_message_thread_id() {
+    const RESPONSE: &str = r#"{"ok":true,"result":{"message_id":78}}"#;
+    let (api, server) = mock_server(&[RESPONSE, RESPONSE]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    bot.send_text("ok".into(), 1234, None).await.unwrap();
+    bot.send_text("ok".into(), 1234, Some(9)).await.unwrap();
+    let (requests, _) = server.join().unwrap();
+    assert!(!requests[0].contains("name=\"message_thread_id\""));
+    assert!(requests[1].contains("name=\"message_thread_id\"\r\n\r\n9\r\n"));
+}
+
+#[tokio::test]
+async fn test_bot_reuses_connection() {
+    const RESPONSE: &str = r#"{"ok":true,"result":{"message_id":78}}"#;
+    let (api, server) = mock_server(&[RESPONSE, RESPONSE, RESPONSE]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    for _ in 0..3 {
+        bot.send_text("ok".into(), 1234, None).await.unwrap();
+    }
+    let (requests, connections) = server.join().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(connections, 1);
+}
+
+#[tokio::test]
+async fn test_get_updates() {
+    let (api, server) = mock_server(&[r#"{
+        "ok": true,
+        "result": [
+            {
+                "update_id": 120,
+                "message": {
+                    "message_id": 5,
+                    "from": { "id": 1111111, "is_bot": false, "first_name": "Eddie" },
+                    "chat": { "id": 1111111, "type": "private" },
+                    "date": 1756400000,
+                    "text": "enter"
+                }
+            },
+            {
+                "update_id": 121,
+                "message": {
+                    "message_id": 6,
+                    "from": { "id": 1111111, "is_bot": false, "first_name": "Eddie" },
+                    "chat": { "id": 1111111, "type": "private" },
+                    "date": 1756403600,
+                    "text": "leave"
+                }
+            }
+        ]
+    }"#]);
+    let bot = Bot::new("TOKEN").with_api(api);
+    let updates = bot.get_updates(Some(120), 30, &["message"]).await.unwrap();
+    assert_eq!(updates.len(), 2);
+    assert_eq!(
+        updates[1].message.as_ref().unwrap().text.as_deref(),
+        Some("leave")
+    );
+    assert_eq!(ack_offset(&updates), Some(122));
+    assert_eq!(ack_offset(&[]), None);
+    let (requests, _) = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /botTOKEN/getUpdates "));
+    assert!(requests[0].contains("name=\"offset\"\r\n\r\n120\r\n"));
+    assert!(requests[0].contains("name=\"timeout\"\r\n\r\n30\r\n"));
+    assert!(requests[0].contains("name=\"allowed_updates\"\r\n\r\n[\"message\"]\r\n"));
+}
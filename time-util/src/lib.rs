@@ -1,5 +1,6 @@
 use chrono::{
-    DateTime, Datelike, Days, Months, NaiveDate, NaiveTime, TimeDelta, TimeZone, Timelike, Weekday,
+    DateTime, Datelike, Days, LocalResult, Months, NaiveDate, NaiveTime, Offset, TimeDelta,
+    TimeZone, Timelike, Weekday,
 };
 use serde::{Deserialize, Serialize};
 use std::{fmt::Display, ops::Range};
@@ -27,17 +28,46 @@ pub enum TimeHintMinute {
 #[derive(Debug, Clone, Copy)]
 pub enum TimeHintDay {
     None,
+    /// The day before the one of the reference instant
+    Yesterday,
     Weekday(Weekday),
     Day(u32),
     MonthDay(u32, u32),
     YearMonthDay(i32, u32, u32),
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum TimeHintWeek {
+    None,
+    Last,
+    /// ISO 8601 year and week number
+    YearWeek(i32, u32),
+}
+
+/// First day of the week
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WeekStart {
+    #[default]
+    Monday,
+    Sunday,
+}
+
+impl WeekStart {
+    pub fn weekday(self) -> Weekday {
+        match self {
+            Self::Monday => Weekday::Mon,
+            Self::Sunday => Weekday::Sun,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum TimeHintMonth {
     None,
     Month(u32),
     YearMonth(i32, u32),
+    /// Months relative to the current one, negative ones are in the past
+    MonthOffset(i32),
 }
 
 pub trait TimeZoneExt: TimeZone + Clone {
@@ -54,7 +84,9 @@ pub trait TimeZoneExt: TimeZone + Clone {
 
 pub trait DateTimeExt<T: TimeZone>: Sized {
     fn format_ymd(self, sep: &'static str) -> Formatter<T>;
+    fn format_date(self, sep: &'static str, order: DateOrder) -> Formatter<T>;
     fn format_hm(self, sep: &'static str) -> Formatter<T>;
+    fn format_time(self, sep: &'static str, clock: Clock) -> Formatter<T>;
 
     fn align_year(self) -> Option<Self>;
     fn range_year(self) -> Option<Range<i64>>;
@@ -73,20 +105,80 @@ pub struct Formatter<T: TimeZone> {
     instant: DateTime<T>,
 }
 pub enum Format {
-    YearMonthDay { sep: &'static str },
-    HourMinute { sep: &'static str },
+    Date { sep: &'static str, order: DateOrder },
+    HourMinute { sep: &'static str, clock: Clock },
+}
+/// Convention for the hours of the day
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clock {
+    /// From `0h00` to `23h59`
+    #[default]
+    H24,
+    /// From `12:00 AM` to `11:59 PM`
+    H12,
+}
+impl Clock {
+    fn write(
+        self,
+        f: &mut std::fmt::Formatter<'_>,
+        sep: &str,
+        hour: u32,
+        minute: u32,
+    ) -> std::fmt::Result {
+        match self {
+            Clock::H24 => write!(f, "{hour}{sep}{minute:0>2}"),
+            Clock::H12 => {
+                let suffix = if hour < 12 { "AM" } else { "PM" };
+                let hour = match hour % 12 {
+                    0 => 12,
+                    hour => hour,
+                };
+                write!(f, "{hour}{sep}{minute:0>2} {suffix}")
+            }
+        }
+    }
+}
+/// Order of the fields of a date, the year is never abbreviated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DateOrder {
+    #[default]
+    Ymd,
+    Dmy,
+    Mdy,
+}
+impl DateOrder {
+    fn write(
+        self,
+        f: &mut std::fmt::Formatter<'_>,
+        sep: &str,
+        year: i32,
+        month: u32,
+        day: u32,
+    ) -> std::fmt::Result {
+        match self {
+            DateOrder::Ymd => write!(f, "{year}{sep}{month:0>2}{sep}{day:0>2}"),
+            DateOrder::Dmy => write!(f, "{day:0>2}{sep}{month:0>2}{sep}{year}"),
+            DateOrder::Mdy => write!(f, "{month:0>2}{sep}{day:0>2}{sep}{year}"),
+        }
+    }
 }
 
 impl<T: TimeZone> DateTimeExt<T> for DateTime<T> {
     fn format_ymd(self, sep: &'static str) -> Formatter<T> {
+        self.format_date(sep, DateOrder::Ymd)
+    }
+    fn format_date(self, sep: &'static str, order: DateOrder) -> Formatter<T> {
         Formatter {
-            format: Format::YearMonthDay { sep },
+            format: Format::Date { sep, order },
             instant: self,
         }
     }
     fn format_hm(self, sep: &'static str) -> Formatter<T> {
+        self.format_time(sep, Clock::H24)
+    }
+    fn format_time(self, sep: &'static str, clock: Clock) -> Formatter<T> {
         Formatter {
-            format: Format::HourMinute { sep },
+            format: Format::HourMinute { sep, clock },
             instant: self,
         }
     }
@@ -181,10 +273,9 @@ impl<T: TimeZone> Iterator for SpanSplitOnDay<T> {
         if self.span.start >= self.span.end {
             return None;
         }
-        let midnight = NaiveTime::from_hms_opt(0, 0, 0)?;
-        let start = self.time_zone.instant(self.span.start);
-        let prev_midnight = start.with_time(midnight).earliest()?;
-        let next_midnight = (prev_midnight + Days::new(1)).timestamp();
+        // days are not always 24h long, the next local midnight is resolved from its date
+        let date = self.time_zone.instant(self.span.start).date_naive();
+        let next_midnight = start_of_day(&self.time_zone, date.succ_opt()?);
 
         if self.span.end <= next_midnight {
             let span = self.span.start..self.span.end;
@@ -198,6 +289,23 @@ impl<T: TimeZone> Iterator for SpanSplitOnDay<T> {
     }
 }
 
+/// First instant of the local day, which is the transition itself when midnight is skipped
+fn start_of_day<T: TimeZone>(time_zone: &T, date: NaiveDate) -> i64 {
+    let midnight = date.and_time(NaiveTime::MIN);
+    match time_zone.from_local_datetime(&midnight) {
+        LocalResult::Single(instant) | LocalResult::Ambiguous(instant, _) => instant.timestamp(),
+        LocalResult::None => {
+            // the gap starts at midnight in the offset in effect the day before
+            let offset = time_zone
+                .offset_from_utc_datetime(&(midnight - TimeDelta::days(1)))
+                .fix();
+            (midnight - TimeDelta::seconds(offset.local_minus_utc().into()))
+                .and_utc()
+                .timestamp()
+        }
+    }
+}
+
 // TODO: remove Date and Time
 impl From<NaiveDate> for Date {
     fn from(date: NaiveDate) -> Self {
@@ -273,14 +381,62 @@ impl TimeHintMonth {
                 .with_ymd_and_hms(year, month, 1, 0, 0, 0)
                 .single()?
                 .range_month()?,
+            Self::MonthOffset(offset) => {
+                let aligned = time_zone.instant(instant).align_month()?;
+                let months = Months::new(offset.unsigned_abs());
+                if offset < 0 {
+                    aligned.checked_sub_months(months)?.range_month()?
+                } else {
+                    aligned.checked_add_months(months)?.range_month()?
+                }
+            }
         })
     }
 }
+impl TimeHintWeek {
+    /// Range from the local midnight starting a week to the next one
+    ///
+    /// Weeks starting on sunday are numbered after the ISO week they mostly overlap.
+    pub fn infer(
+        self,
+        time_zone: impl TimeZone,
+        instant: i64,
+        week_start: WeekStart,
+    ) -> Option<Range<i64>> {
+        let first = week_start.weekday();
+        let start = match self {
+            Self::None => {
+                let day = time_zone.instant(instant).align_day()?;
+                let offset = day.weekday().days_since(first);
+                day.checked_sub_days(Days::new(offset as u64))?
+            }
+            Self::Last => {
+                let day = time_zone.instant(instant).align_day()?;
+                let offset = day.weekday().days_since(first) + 7;
+                day.checked_sub_days(Days::new(offset as u64))?
+            }
+            Self::YearWeek(year, week) => {
+                let mut date = NaiveDate::from_isoywd_opt(year, week, Weekday::Mon)?;
+                if week_start == WeekStart::Sunday {
+                    date = date.pred_opt()?;
+                }
+                time_zone
+                    .from_local_datetime(&date.and_time(NaiveTime::MIN))
+                    .earliest()?
+            }
+        };
+        let end = start.clone().checked_add_days(Days::new(7))?;
+        Some(start.timestamp()..end.timestamp())
+    }
+}
 impl TimeHintDay {
     // TODO: rename to infer_before
     pub fn infer_past(self, time_zone: impl TimeZone, instant: i64) -> Option<Range<i64>> {
         Some(match self {
             TimeHintDay::None => time_zone.instant(instant).align_day()?.range_day()?,
+            TimeHintDay::Yesterday => (time_zone.instant(instant).align_day()? - Days::new(1))
+                .align_day()?
+                .range_day()?,
             TimeHintDay::Weekday(hint) => {
                 let mut aligned_day = time_zone.instant(instant).align_day()?;
                 while aligned_day.weekday() != hint {
@@ -307,7 +463,7 @@ impl TimeHintDay {
     }
     pub fn infer_first_after(self, time_zone: impl TimeZone, instant: i64) -> Option<Range<i64>> {
         match self {
-            TimeHintDay::None => None,
+            TimeHintDay::None | TimeHintDay::Yesterday => None,
             TimeHintDay::Weekday(weekday) => {
                 let mut aligned_day = time_zone.instant(instant).align_day()?;
                 while aligned_day.weekday() != weekday {
@@ -353,53 +509,62 @@ impl TimeHintDay {
 pub struct TimeDisplayHourMinute {
     time: Time,
     sep: &'static str,
+    clock: Clock,
 }
 impl Display for TimeDisplayHourMinute {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}{:0>2}", self.time.hour, self.sep, self.time.minute)
+        self.clock
+            .write(f, self.sep, self.time.hour, self.time.minute)
     }
 }
 impl Time {
     pub fn display_hm(self, sep: &'static str) -> TimeDisplayHourMinute {
-        TimeDisplayHourMinute { time: self, sep }
+        self.display(sep, Clock::H24)
+    }
+    pub fn display(self, sep: &'static str, clock: Clock) -> TimeDisplayHourMinute {
+        TimeDisplayHourMinute {
+            time: self,
+            sep,
+            clock,
+        }
     }
 }
 pub struct DateDisplayYearMonthDay {
     date: Date,
     sep: &'static str,
+    order: DateOrder,
 }
 impl Display for DateDisplayYearMonthDay {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}{}{:0>2}{}{:0>2}",
-            self.date.year, self.sep, self.date.month, self.sep, self.date.day
-        )
+        self.order
+            .write(f, self.sep, self.date.year, self.date.month, self.date.day)
     }
 }
 impl Date {
     pub fn display_ymd(self, sep: &'static str) -> DateDisplayYearMonthDay {
-        DateDisplayYearMonthDay { date: self, sep }
+        self.display(sep, DateOrder::Ymd)
+    }
+    pub fn display(self, sep: &'static str, order: DateOrder) -> DateDisplayYearMonthDay {
+        DateDisplayYearMonthDay {
+            date: self,
+            sep,
+            order,
+        }
     }
 }
 
 impl<T: TimeZone> Display for Formatter<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.format {
-            Format::YearMonthDay { sep } => write!(
+            Format::Date { sep, order } => order.write(
                 f,
-                "{}{sep}{:0>2}{sep}{:0>2}",
+                sep,
                 self.instant.year(),
                 self.instant.month(),
-                self.instant.day()
+                self.instant.day(),
             ),
-            Format::HourMinute { sep } => {
-                write!(
-                    f,
-                    "{}{sep}{:0>2}",
-                    self.instant.hour(),
-                    self.instant.minute(),
-                )
+            Format::HourMinute { sep, clock } => {
+                clock.write(f, sep, self.instant.hour(), self.instant.minute())
             }
         }
     }
@@ -422,3 +587,233 @@ fn test_time_hint_month() {
         Some(month_start..month_end)
     );
 }
+
+#[test]
+fn test_time_hint_month_offset() {
+    use chrono::Utc;
+    let ymd = |year, month, day| {
+        Utc.with_ymd_and_hms(year, month, day, 0, 0, 0)
+            .single()
+            .unwrap()
+            .timestamp()
+    };
+    let instant = ymd(2025, 1, 15) + 8 * 60 * 60;
+    assert_eq!(
+        TimeHintMonth::MonthOffset(-1).infer(Utc, instant),
+        Some(ymd(2024, 12, 1)..ymd(2025, 1, 1))
+    );
+    assert_eq!(
+        TimeHintMonth::MonthOffset(-13).infer(Utc, instant),
+        Some(ymd(2023, 12, 1)..ymd(2024, 1, 1))
+    );
+    assert_eq!(
+        TimeHintMonth::MonthOffset(0).infer(Utc, instant),
+        TimeHintMonth::None.infer(Utc, instant)
+    );
+    assert_eq!(
+        TimeHintMonth::MonthOffset(1).infer(Utc, instant),
+        Some(ymd(2025, 2, 1)..ymd(2025, 3, 1))
+    );
+}
+
+#[test]
+fn test_time_hint_week() {
+    use chrono::Utc;
+    let ymd_hms = |year, month, day, hour, minute, second| {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, second)
+            .single()
+            .unwrap()
+            .timestamp()
+    };
+    // a thursday
+    let instant = ymd_hms(2025, 8, 21, 20, 15, 0);
+    let monday = ymd_hms(2025, 8, 18, 0, 0, 0);
+    let next_monday = ymd_hms(2025, 8, 25, 0, 0, 0);
+    let last_monday = ymd_hms(2025, 8, 11, 0, 0, 0);
+    assert_eq!(
+        TimeHintWeek::None.infer(Utc, instant, WeekStart::Monday),
+        Some(monday..next_monday)
+    );
+    assert_eq!(
+        TimeHintWeek::None.infer(Utc, monday, WeekStart::Monday),
+        Some(monday..next_monday)
+    );
+    assert_eq!(
+        TimeHintWeek::Last.infer(Utc, instant, WeekStart::Monday),
+        Some(last_monday..monday)
+    );
+    assert_eq!(
+        TimeHintWeek::YearWeek(2025, 34).infer(Utc, instant, WeekStart::Monday),
+        Some(monday..next_monday)
+    );
+    assert_eq!(
+        TimeHintWeek::YearWeek(2025, 54).infer(Utc, instant, WeekStart::Monday),
+        None
+    );
+}
+
+#[test]
+fn test_time_hint_yesterday() {
+    use chrono::Utc;
+    let ymd_hms = |year, month, day, hour, minute, second| {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, second)
+            .single()
+            .unwrap()
+            .timestamp()
+    };
+    let instant = ymd_hms(2025, 3, 1, 20, 15, 0);
+    assert_eq!(
+        TimeHintDay::Yesterday.infer_past(Utc, instant),
+        Some(ymd_hms(2025, 2, 28, 0, 0, 0)..ymd_hms(2025, 3, 1, 0, 0, 0))
+    );
+    assert_eq!(TimeHintDay::Yesterday.infer_first_after(Utc, instant), None);
+}
+
+#[test]
+fn test_time_hint_week_start() {
+    use chrono::Utc;
+    let ymd = |year, month, day| {
+        Utc.with_ymd_and_hms(year, month, day, 0, 0, 0)
+            .single()
+            .unwrap()
+            .timestamp()
+    };
+    // a sunday, last day of ISO week 34
+    let sunday = ymd(2025, 8, 24) + 10 * 60 * 60;
+    let monday = ymd(2025, 8, 18);
+    assert_eq!(
+        TimeHintWeek::None.infer(Utc, sunday, WeekStart::Monday),
+        Some(monday..ymd(2025, 8, 25))
+    );
+    assert_eq!(
+        TimeHintWeek::None.infer(Utc, sunday, WeekStart::Sunday),
+        Some(ymd(2025, 8, 24)..ymd(2025, 8, 31))
+    );
+    assert_eq!(
+        TimeHintWeek::Last.infer(Utc, sunday, WeekStart::Sunday),
+        Some(ymd(2025, 8, 17)..ymd(2025, 8, 24))
+    );
+    // the saturday before still belongs to the week started the previous sunday
+    let saturday = ymd(2025, 8, 23) + 10 * 60 * 60;
+    assert_eq!(
+        TimeHintWeek::None.infer(Utc, saturday, WeekStart::Sunday),
+        Some(ymd(2025, 8, 17)..ymd(2025, 8, 24))
+    );
+    assert_eq!(
+        TimeHintWeek::YearWeek(2025, 34).infer(Utc, sunday, WeekStart::Sunday),
+        Some(ymd(2025, 8, 17)..ymd(2025, 8, 24))
+    );
+}
+
+#[test]
+fn test_split_span_on_day_dst() {
+    use chrono_tz::Tz;
+    let check = |time_zone: Tz, span: Range<i64>, days: usize| {
+        let chunks: Vec<_> = time_zone.split_span_on_day(span.clone()).collect();
+        assert_eq!(chunks.len(), days);
+        assert_eq!(chunks.first().unwrap().start, span.start);
+        assert_eq!(chunks.last().unwrap().end, span.end);
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        for chunk in &chunks {
+            assert!(chunk.start < chunk.end);
+            let first = time_zone.instant(chunk.start).date_naive();
+            let last = time_zone.instant(chunk.end - 1).date_naive();
+            assert_eq!(first, last);
+        }
+        chunks
+    };
+    let madrid = |year, month, day, hour| {
+        Tz::Europe__Madrid
+            .with_ymd_and_hms(year, month, day, hour, 0, 0)
+            .single()
+            .unwrap()
+            .timestamp()
+    };
+    // spring forward, the 30th lasts 23h
+    let chunks = check(
+        Tz::Europe__Madrid,
+        madrid(2025, 3, 29, 20)..madrid(2025, 3, 31, 4),
+        3,
+    );
+    assert_eq!(chunks[1], madrid(2025, 3, 30, 0)..madrid(2025, 3, 31, 0));
+    assert_eq!(chunks[1].end - chunks[1].start, 23 * 60 * 60);
+    // fall back, the 26th lasts 25h
+    let chunks = check(
+        Tz::Europe__Madrid,
+        madrid(2025, 10, 25, 20)..madrid(2025, 10, 27, 4),
+        3,
+    );
+    assert_eq!(chunks[1], madrid(2025, 10, 26, 0)..madrid(2025, 10, 27, 0));
+    assert_eq!(chunks[1].end - chunks[1].start, 25 * 60 * 60);
+
+    // in Santiago the clocks change at midnight itself
+    let santiago = |year, month, day, hour| {
+        Tz::America__Santiago
+            .with_ymd_and_hms(year, month, day, hour, 0, 0)
+            .earliest()
+            .unwrap()
+            .timestamp()
+    };
+    // midnight is skipped, the 8th starts at 01:00
+    let chunks = check(
+        Tz::America__Santiago,
+        santiago(2024, 9, 7, 20)..santiago(2024, 9, 8, 4),
+        2,
+    );
+    assert_eq!(chunks[1].start, santiago(2024, 9, 8, 1));
+    // the hour before midnight happens twice, the 7th still starts at midnight
+    let chunks = check(
+        Tz::America__Santiago,
+        santiago(2024, 4, 6, 20)..santiago(2024, 4, 7, 4),
+        2,
+    );
+    assert_eq!(chunks[1].start, santiago(2024, 4, 7, 0));
+}
+
+#[test]
+fn test_date_order() {
+    use chrono::Utc;
+    let instant = Utc.with_ymd_and_hms(2025, 3, 7, 0, 0, 0).single().unwrap();
+    let date = Date {
+        year: 2025,
+        month: 3,
+        day: 7,
+    };
+    for (order, expected) in [
+        (DateOrder::Ymd, "2025/03/07"),
+        (DateOrder::Dmy, "07/03/2025"),
+        (DateOrder::Mdy, "03/07/2025"),
+    ] {
+        assert_eq!(instant.format_date("/", order).to_string(), expected);
+        assert_eq!(date.display("/", order).to_string(), expected);
+    }
+    assert_eq!(instant.format_ymd("-").to_string(), "2025-03-07");
+}
+
+#[test]
+fn test_clock() {
+    use chrono::Utc;
+    for (hour, minute, h24, h12) in [
+        (0, 30, "0h30", "12:30 AM"),
+        (12, 0, "12h00", "12:00 PM"),
+        (13, 5, "13h05", "1:05 PM"),
+        (23, 59, "23h59", "11:59 PM"),
+    ] {
+        let instant = Utc
+            .with_ymd_and_hms(2025, 3, 7, hour, minute, 0)
+            .single()
+            .unwrap();
+        let time = Time {
+            hour,
+            minute,
+            second: 0,
+        };
+        assert_eq!(instant.format_time("h", Clock::H24).to_string(), h24);
+        assert_eq!(instant.format_time(":", Clock::H12).to_string(), h12);
+        assert_eq!(time.display("h", Clock::H24).to_string(), h24);
+        assert_eq!(time.display(":", Clock::H12).to_string(), h12);
+        assert_eq!(instant.format_hm("h").to_string(), h24);
+    }
+}